@@ -1,71 +1,794 @@
-use bevy::prelude::*;
+use std::collections::HashMap;
 
-use crate::AppState;
-use crate::TILE_SIZE;
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::BoxedFuture,
+};
 
-pub struct AsciiPlugin;
+use crate::autotile::{ascii_autotiles, Terrain};
+use crate::components::Collider;
+use crate::tilemap::{
+    MapObject, PropertyValue, Tile, TileAnimation, TileFrame, TileLayer, TileMap,
+    TileMapLoaderError, Tileset, TilesetDescription,
+};
+use crate::{ASCII_SHEET, TILE_SIZE};
 
-#[derive(Resource)]
-pub struct AsciiSheet {
-    texture: Handle<Image>,
-    layout: Handle<TextureAtlasLayout>,
+const ASCII_TILESET: &str = "ascii"; // Name of the ASCII sheet in the [tilesets] header
+
+// How one character of an ASCII map is drawn and what it does
+#[derive(Debug, Clone)]
+pub struct LegendEntry {
+    pub glyph: Option<usize>, // None leaves the cell empty
+    pub color: Color,
+    pub solid: bool,
+    pub spawn: Option<String>, // Map object type spawned in the cell, e.g. "player" or "mole"
+    pub name: String,          // Name given to the spawned object
+    pub properties: HashMap<String, PropertyValue>, // Passed on to the spawned object
+    pub animation: Option<TileAnimation>, // Frames the glyph cycles through
+    pub terrain: Option<Terrain>, // Replaces the glyph with the one fitting its neighbours
+    pub collider: Option<Collider>, // Solid part of the cell when it is not the whole cell
+    pub traction: Option<f32>, // Grip of the ground, 1 for plain ground
 }
 
-impl Plugin for AsciiPlugin {
-    fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(AppState::Preload), load_ascii);
+// Character to legend entry mapping, read from the [legend] header of the map file
+#[derive(Debug, Clone)]
+pub struct AsciiLegend(HashMap<char, LegendEntry>);
+
+impl Default for AsciiLegend {
+    // Maps without a legend draw every glyph and only '#' is solid, drawn as walls
+    fn default() -> Self {
+        let mut legend = AsciiLegend(HashMap::new());
+        legend.0.insert(
+            '#',
+            LegendEntry {
+                solid: true,
+                terrain: Some(Terrain::Wall),
+                ..LegendEntry::plain('#')
+            },
+        );
+        legend
     }
 }
 
-pub fn spawn_ascii_sprite(
-    commands: &mut Commands,
-    ascii: &AsciiSheet,
-    index: usize,
-    color: Color,
-    translation: Vec3,
-    texture_atlas_layout: Handle<TextureAtlasLayout>,
-) -> Entity {
-    assert!(index < 256, "Index out of ASCII range!");
-
-    commands
-        .spawn(SpriteSheetBundle {
-            sprite: Sprite {
-                custom_size: Some(Vec2::new(TILE_SIZE, TILE_SIZE)),
-                color: color,
-                ..default()
-            },
-            texture: ascii.texture.clone(),
-            atlas: TextureAtlas {
-                index: index,
-                layout: texture_atlas_layout,
-                ..default()
-            },
-            transform: Transform {
-                translation: translation,
-                ..default()
-            },
-            ..default()
+impl LegendEntry {
+    // A character that is not in the legend is drawn as itself
+    fn plain(char: char) -> Self {
+        LegendEntry {
+            glyph: (char != ' ').then_some(char as usize),
+            color: Color::rgb(0.9, 0.9, 0.9),
+            solid: false,
+            spawn: None,
+            name: String::new(),
+            properties: HashMap::new(),
+            animation: None,
+            terrain: None,
+            collider: None,
+            traction: None,
+        }
+    }
+
+    // Parse the options after the '=' of a legend line, e.g. "glyph 35, color #ffffff, solid"
+    fn parse(char: char, options: &str) -> Result<Self, String> {
+        let mut entry = LegendEntry::plain(char);
+        for option in options.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+            let (key, value) = match option.split_once(' ') {
+                Some((key, value)) => (key, value.trim()),
+                None => (option, ""),
+            };
+            match key {
+                "glyph" if value == "none" => entry.glyph = None,
+                "glyph" => entry.glyph = Some(parse_glyph(value)?),
+                "color" => {
+                    entry.color =
+                        Color::hex(value).map_err(|_| format!("invalid color '{}'", value))?
+                }
+                "solid" => entry.solid = true,
+                "traction" => {
+                    entry.traction = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("invalid traction '{}'", value))?,
+                    )
+                }
+                "collider" => {
+                    entry.solid = true;
+                    entry.collider = Some(parse_collider(value)?);
+                }
+                "terrain" => {
+                    entry.terrain = Some(
+                        Terrain::from_name(value)
+                            .ok_or_else(|| format!("unknown terrain '{}'", value))?,
+                    )
+                }
+                "spawn" if !value.is_empty() => entry.spawn = Some(value.to_string()),
+                "name" => entry.name = value.to_string(),
+                "property" => {
+                    let (name, value) = value
+                        .split_once(' ')
+                        .ok_or_else(|| "expected 'property <name> <value>'".to_string())?;
+                    entry.properties.insert(
+                        name.to_string(),
+                        PropertyValue::String(value.trim().to_string()),
+                    );
+                }
+                "frames" => {
+                    let mut values = value.split_whitespace();
+                    let duration: f32 = values
+                        .next()
+                        .and_then(|duration| duration.parse().ok())
+                        .ok_or_else(|| "expected 'frames <seconds> <glyph>...'".to_string())?;
+                    let frames = values
+                        .map(|glyph| parse_glyph(glyph).map(|index| TileFrame { index, duration }))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    if frames.is_empty() {
+                        return Err("an animation needs at least one frame".to_string());
+                    }
+                    entry.animation = Some(TileAnimation { frames });
+                }
+                _ => return Err(format!("unknown option '{}'", option)),
+            }
+        }
+        Ok(entry)
+    }
+}
+
+// A glyph is either a GID or a quoted character from the ASCII sheet, e.g. 35 or '#'.
+// GIDs 0 to 255 are the ASCII sheet, higher ones come from the map's [tilesets].
+fn parse_glyph(value: &str) -> Result<usize, String> {
+    match value.chars().collect::<Vec<_>>()[..] {
+        ['\'', glyph, '\''] if (glyph as usize) < 256 => Ok(glyph as usize),
+        ['\'', glyph, '\''] => Err(format!("'{}' is outside the ASCII range", glyph)),
+        _ => value
+            .parse()
+            .map_err(|_| format!("invalid glyph '{}'", value)),
+    }
+}
+
+// A partial collider in map pixels from the center of the cell, y pointing down, e.g.
+// "box 32 16 0 -8" for the top half of a 32 pixel cell or "circle 8" for a post
+fn parse_collider(value: &str) -> Result<Collider, String> {
+    let numbers = value
+        .split_whitespace()
+        .skip(1)
+        .map(|number| {
+            number
+                .parse::<f32>()
+                .map_err(|_| format!("invalid number '{}'", number))
         })
-        .id()
+        .collect::<Result<Vec<_>, _>>()?;
+    let (collider, offset) = match (value.split_whitespace().next(), &numbers[..]) {
+        (Some("box"), [w, h, offset @ ..]) => (Collider::aabb(Vec2::new(*w, *h) / 2.), offset),
+        (Some("circle"), [radius, offset @ ..]) => (Collider::circle(*radius), offset),
+        _ => {
+            return Err(format!(
+                "expected 'collider box <w> <h> [<x> <y>]' or 'collider circle <r> [<x> <y>]', found '{}'",
+                value
+            ))
+        }
+    };
+    match offset {
+        [] => Ok(collider),
+        [x, y] => Ok(collider.with_offset(Vec2::new(*x, *y))),
+        _ => Err(format!("expected an x and y offset, found '{}'", value)),
+    }
+}
+
+// Grid of the ASCII sheet, which is always the first tileset of ASCII maps. Maps drawn on
+// another sheet describe it in their [tilesets] header under this name.
+pub fn ascii_tileset_description() -> TilesetDescription {
+    TilesetDescription {
+        name: ASCII_TILESET.to_string(),
+        image: ASCII_SHEET.to_string(),
+        tile_size: Vec2::new(9., 9.),
+        columns: 16,
+        rows: 16,
+        padding: Vec2::new(2., 2.),
+        offset: Vec2::ZERO,
+        first_gid: 0,
+    }
+}
+
+// Parse the options after the '=' of a tileset line, e.g.
+// "image tileset.png, tile 16 16, columns 34, rows 32, padding 1 1, offset 0 0, first 256".
+// The ASCII sheet starts from its default grid, so only what differs needs to be given.
+fn parse_tileset(name: &str, options: &str) -> Result<TilesetDescription, String> {
+    let mut tileset = if name == ASCII_TILESET {
+        ascii_tileset_description()
+    } else {
+        TilesetDescription {
+            name: name.to_string(),
+            image: String::new(),
+            tile_size: Vec2::ZERO,
+            columns: 0,
+            rows: 0,
+            padding: Vec2::ZERO,
+            offset: Vec2::ZERO,
+            first_gid: 0,
+        }
+    };
+    let number = |value: &str| {
+        value
+            .parse::<u32>()
+            .map_err(|_| format!("invalid number '{}'", value))
+    };
+    let pair = |value: &str| match value.split_whitespace().collect::<Vec<_>>()[..] {
+        [x, y] => Ok(Vec2::new(number(x)? as f32, number(y)? as f32)),
+        _ => Err(format!("expected two numbers, found '{}'", value)),
+    };
+
+    for option in options.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+        let (key, value) = option.split_once(' ').unwrap_or((option, ""));
+        let value = value.trim();
+        match key {
+            "image" => tileset.image = value.to_string(),
+            "tile" => tileset.tile_size = pair(value)?,
+            "columns" => tileset.columns = number(value)? as usize,
+            "rows" => tileset.rows = number(value)? as usize,
+            "padding" => tileset.padding = pair(value)?,
+            "offset" => tileset.offset = pair(value)?,
+            "first" => tileset.first_gid = number(value)?,
+            _ => return Err(format!("unknown option '{}'", option)),
+        }
+    }
+
+    if tileset.image.is_empty() || tileset.tile_size == Vec2::ZERO {
+        return Err("a tileset needs at least an image and a tile size".to_string());
+    }
+    if tileset.columns == 0 || tileset.rows == 0 {
+        return Err("a tileset needs at least one column and row".to_string());
+    }
+    // Quoted characters are GIDs of the ASCII sheet, so it holds the first 256 of them
+    if name == ASCII_TILESET && (tileset.first_gid != 0 || tileset.tile_count() < 256) {
+        return Err("the ascii tileset must start at GID 0 and hold 256 glyphs".to_string());
+    }
+    Ok(tileset)
+}
+
+impl AsciiLegend {
+    pub fn entry(&self, char: char) -> LegendEntry {
+        self.0
+            .get(&char)
+            .cloned()
+            .unwrap_or_else(|| LegendEntry::plain(char))
+    }
+}
+
+// The sections of an ASCII map file
+struct AsciiMapFile<'a> {
+    tilesets: Vec<TilesetDescription>, // The ASCII sheet first, then the ones declared in the file
+    legend: AsciiLegend,
+    rows: Vec<&'a str>,
+}
+
+// Split a map file into its tilesets, legend and map rows. Both headers are optional, image
+// paths are relative to the assets folder:
+//
+// [tilesets]
+// ascii = image tilemap/Ascii.png, tile 9 9, columns 16, rows 16, padding 2 2
+// forest = image forest/tileset.png, tile 16 16, columns 34, rows 32, first 256
+// [legend]
+// # = glyph 35, color #e6e6e6, solid, terrain wall
+// @ = glyph none, spawn player, name start
+// m = color #a0522d, spawn mole
+// E = glyph 'E', spawn exit, property map maps/cave.txt
+// ~ = color #4080ff, solid, frames 0.4 '~' '-'
+// - = glyph 196, collider box 32 16 0 -8
+// _ = glyph 176, color #c0e8ff, traction 0.15
+// " = glyph 881, color #ffffff
+// [map]
+// #######
+// #@ m~~#
+// #"""" #
+// #---  #
+// #######
+fn parse_ascii_map(text: &str) -> Result<AsciiMapFile<'_>, TileMapLoaderError> {
+    let mut file = AsciiMapFile {
+        tilesets: vec![ascii_tileset_description()],
+        legend: AsciiLegend::default(),
+        rows: Vec::new(),
+    };
+    let is_header = |line: &str| line.trim().starts_with('[') && line.trim().ends_with(']');
+    if !text.lines().next().is_some_and(is_header) {
+        file.rows = text.lines().collect();
+        return Ok(file);
+    }
+
+    let mut section = "";
+    for (number, line) in text.lines().enumerate() {
+        if section == "map" {
+            file.rows.push(line);
+            continue;
+        }
+        let invalid = |reason: String| {
+            TileMapLoaderError::Invalid(format!("line {}: {}", number + 1, reason))
+        };
+        if is_header(line) {
+            section = line.trim().trim_start_matches('[').trim_end_matches(']');
+            match section {
+                // A legend replaces the default one instead of adding to it
+                "legend" => file.legend = AsciiLegend(HashMap::new()),
+                "tilesets" | "map" => {}
+                _ => return Err(invalid(format!("unknown section [{}]", section))),
+            }
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (key, options) = line
+            .split_once('=')
+            .ok_or_else(|| invalid("expected '<key> = <options>'".to_string()))?;
+        match section {
+            "tilesets" => {
+                let tileset = parse_tileset(key.trim(), options).map_err(invalid)?;
+                if tileset.name == ASCII_TILESET {
+                    file.tilesets[0] = tileset;
+                } else {
+                    file.tilesets.push(tileset);
+                }
+            }
+            _ => {
+                // The key of a legend line is a single character, which may itself be '='
+                let mut chars = line.chars();
+                let char = chars.next().unwrap_or(' ');
+                let options = chars
+                    .as_str()
+                    .trim_start()
+                    .strip_prefix('=')
+                    .ok_or_else(|| invalid(format!("expected '{} = <options>'", char)))?;
+                file.legend
+                    .0
+                    .insert(char, LegendEntry::parse(char, options).map_err(invalid)?);
+            }
+        }
+    }
+
+    if section != "map" {
+        return Err(TileMapLoaderError::Invalid(
+            "map file has no [map] section".to_string(),
+        ));
+    }
+    Ok(file)
+}
+
+// The ASCII sheet as a tileset for maps drawn with glyphs, walls and cliffs autotile
+// to box drawing characters
+pub fn ascii_tileset(description: &TilesetDescription, load_context: &mut LoadContext) -> Tileset {
+    Tileset {
+        autotiles: ascii_autotiles(),
+        ..description.load(load_context, "glyphs".to_string())
+    }
 }
 
-fn load_ascii(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    mut texture_atlases: ResMut<Assets<TextureAtlasLayout>>,
-) {
-    let image: Handle<Image> = asset_server.load("tilemap/Ascii.png");
-    let atlas = TextureAtlasLayout::from_grid(
-        Vec2::new(TILE_SIZE, TILE_SIZE),
-        16,
-        16,
-        Some(Vec2::new(9.0, 9.0)),
-        None,
-    );
-    let atlas_handle = texture_atlases.add(atlas);
-
-    commands.insert_resource(AsciiSheet {
-        texture: image,
-        layout: atlas_handle,
-    });
+// Loads text maps where every character is drawn as a glyph from the ASCII sheet
+#[derive(Default)]
+pub struct AsciiMapLoader;
+
+impl AssetLoader for AsciiMapLoader {
+    type Asset = TileMap;
+    type Settings = ();
+    type Error = TileMapLoaderError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<TileMap, TileMapLoaderError>> {
+        Box::pin(async move {
+            let mut text = String::new();
+            reader.read_to_string(&mut text).await?;
+            let AsciiMapFile {
+                tilesets: descriptions,
+                legend,
+                rows,
+            } = parse_ascii_map(&text)?;
+            let width = rows
+                .iter()
+                .map(|line| line.chars().count())
+                .max()
+                .unwrap_or(0);
+            let mut map = TileMap::new(width as u32, rows.len() as u32, Vec2::splat(TILE_SIZE));
+
+            TilesetDescription::check_overlap(&descriptions)?;
+            map.tilesets
+                .push(ascii_tileset(&descriptions[0], load_context));
+            for (i, description) in descriptions.iter().enumerate().skip(1) {
+                map.tilesets
+                    .push(description.load(load_context, format!("tileset{}", i)));
+            }
+
+            // Glyphs are GIDs, find the tileset and tile they stand for
+            let find = |char: char, gid: usize| {
+                TilesetDescription::find(&descriptions, gid as u32).ok_or_else(|| {
+                    TileMapLoaderError::Invalid(format!(
+                        "'{}' uses glyph {}, which is in none of the tilesets",
+                        char, gid
+                    ))
+                })
+            };
+            for (char, entry) in legend.0.iter() {
+                let (Some(glyph), Some(animation)) = (entry.glyph, &entry.animation) else {
+                    continue;
+                };
+                let (tileset, index) = find(*char, glyph)?;
+                let mut frames = Vec::new();
+                for frame in animation.frames.iter() {
+                    match find(*char, frame.index)? {
+                        (frame_tileset, frame_index) if frame_tileset == tileset => {
+                            frames.push(TileFrame {
+                                index: frame_index,
+                                ..*frame
+                            })
+                        }
+                        _ => {
+                            return Err(TileMapLoaderError::Invalid(format!(
+                                "the frames of '{}' are not all in one tileset",
+                                char
+                            )))
+                        }
+                    }
+                }
+                map.tilesets[tileset]
+                    .animations
+                    .insert(index, TileAnimation { frames });
+            }
+            for (char, entry) in legend.0.iter() {
+                if let (Some(glyph), Some(traction)) = (entry.glyph, entry.traction) {
+                    let (tileset, index) = find(*char, glyph)?;
+                    map.tilesets[tileset].traction.insert(index, traction);
+                }
+            }
+
+            let mut tiles = vec![None; width * rows.len()];
+            for (y, line) in rows.iter().enumerate() {
+                for (x, char) in line.chars().enumerate() {
+                    // Characters outside the legend are drawn as themselves from the ASCII sheet
+                    if !legend.0.contains_key(&char) && char as usize >= 256 {
+                        return Err(TileMapLoaderError::Invalid(format!(
+                            "'{}' at row {}, column {} is outside the ASCII range",
+                            char,
+                            y + 1,
+                            x + 1
+                        )));
+                    }
+                    let entry = legend.entry(char);
+
+                    if let Some(glyph) = entry.glyph {
+                        let (tileset, index) = find(char, glyph)?;
+                        tiles[y * width + x] = Some(Tile {
+                            color: entry.color,
+                            terrain: entry.terrain,
+                            collider: entry.solid.then(|| {
+                                entry
+                                    .collider
+                                    .unwrap_or(Collider::aabb(map.grid.tile_size / 2.))
+                            }),
+                            ..Tile::new(tileset, index)
+                        });
+                    }
+                    map.solid[y * width + x] = entry.solid;
+                    if let Some(collider) = entry.collider {
+                        map.partial_colliders
+                            .insert(IVec2::new(x as i32, y as i32), collider);
+                    }
+
+                    if let Some(kind) = entry.spawn {
+                        map.objects.push(MapObject {
+                            name: entry.name,
+                            kind,
+                            position: (Vec2::new(x as f32, y as f32) + 0.5) * map.grid.tile_size,
+                            size: map.grid.tile_size,
+                            properties: entry.properties.clone(),
+                        });
+                    }
+                }
+            }
+
+            map.layers.push(TileLayer {
+                name: "ascii".to_string(),
+                tiles,
+                visible: true,
+                opacity: 1.,
+            });
+            map.autotile();
+            Ok(map)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["txt"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> String {
+        match parse_ascii_map(text) {
+            Ok(_) => panic!("{:?} should not parse", text),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn glyphs_are_gids_or_quoted_characters() {
+        let cases = [
+            ("35", Ok(35)),
+            ("881", Ok(881)),
+            ("'#'", Ok('#' as usize)),
+            ("'='", Ok('=' as usize)),
+            ("'é'", Ok('é' as usize)),
+            ("'→'", Err("'→' is outside the ASCII range")),
+            ("#", Err("invalid glyph '#'")),
+            ("'ab'", Err("invalid glyph ''ab''")),
+            ("-1", Err("invalid glyph '-1'")),
+            ("", Err("invalid glyph ''")),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(
+                parse_glyph(value),
+                expected.map_err(str::to_string),
+                "{:?}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn colliders_are_boxes_or_circles_with_an_optional_offset() {
+        let cases = [
+            ("box 32 16", Ok(Collider::aabb(Vec2::new(16., 8.)))),
+            (
+                "box 32 16 0 -8",
+                Ok(Collider::aabb(Vec2::new(16., 8.)).with_offset(Vec2::new(0., -8.))),
+            ),
+            ("circle 8", Ok(Collider::circle(8.))),
+            (
+                "circle 4.5 2 2",
+                Ok(Collider::circle(4.5).with_offset(Vec2::splat(2.))),
+            ),
+            ("box 32 sixteen", Err("invalid number 'sixteen'".to_string())),
+            ("box 32", Err("expected 'collider box <w> <h> [<x> <y>]' or 'collider circle <r> [<x> <y>]', found 'box 32'".to_string())),
+            ("square 8", Err("expected 'collider box <w> <h> [<x> <y>]' or 'collider circle <r> [<x> <y>]', found 'square 8'".to_string())),
+            ("circle 8 1", Err("expected an x and y offset, found 'circle 8 1'".to_string())),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_collider(value), expected, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn legend_options_fill_in_the_entry() {
+        let cases = [
+            ("glyph 35, solid", Some(35), true, None, ""),
+            ("glyph '=', solid", Some('=' as usize), true, None, ""),
+            (
+                "glyph none, spawn player, name start",
+                None,
+                false,
+                Some("player"),
+                "start",
+            ),
+            (
+                "color #a0522d, spawn mole",
+                Some('x' as usize),
+                false,
+                Some("mole"),
+                "",
+            ),
+            ("", Some('x' as usize), false, None, ""),
+        ];
+        for (options, glyph, solid, spawn, name) in cases {
+            let entry = LegendEntry::parse('x', options).unwrap();
+            assert_eq!(entry.glyph, glyph, "{:?}", options);
+            assert_eq!(entry.solid, solid, "{:?}", options);
+            assert_eq!(entry.spawn.as_deref(), spawn, "{:?}", options);
+            assert_eq!(entry.name, name, "{:?}", options);
+        }
+
+        let entry = LegendEntry::parse('x', "color #a0522d").unwrap();
+        assert_eq!(entry.color, Color::hex("a0522d").unwrap());
+
+        let entry = LegendEntry::parse('E', "spawn exit, property map maps/cave.txt").unwrap();
+        assert_eq!(
+            entry.properties.get("map"),
+            Some(&PropertyValue::String("maps/cave.txt".to_string()))
+        );
+
+        // A collider makes the cell solid
+        let entry = LegendEntry::parse('-', "glyph 196, collider circle 8").unwrap();
+        assert!(entry.solid);
+        assert_eq!(entry.collider, Some(Collider::circle(8.)));
+
+        let entry = LegendEntry::parse(
+            '~',
+            "solid, terrain water, frames 0.4 '~' '-', traction 0.5",
+        )
+        .unwrap();
+        assert_eq!(entry.traction, Some(0.5));
+        assert_eq!(entry.terrain, Some(Terrain::Water));
+        assert_eq!(
+            entry.animation.unwrap().frames,
+            vec![
+                TileFrame {
+                    index: '~' as usize,
+                    duration: 0.4
+                },
+                TileFrame {
+                    index: '-' as usize,
+                    duration: 0.4
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_legend_options_are_reported() {
+        let cases = [
+            ("glyph x", "invalid glyph 'x'"),
+            ("color blue", "invalid color 'blue'"),
+            ("terrain lava", "unknown terrain 'lava'"),
+            ("traction slippery", "invalid traction 'slippery'"),
+            ("collider box 8", "expected 'collider box <w> <h> [<x> <y>]' or 'collider circle <r> [<x> <y>]', found 'box 8'"),
+            ("property map", "expected 'property <name> <value>'"),
+            ("frames", "expected 'frames <seconds> <glyph>...'"),
+            ("frames 0.4", "an animation needs at least one frame"),
+            ("frames 0.4 '~' x", "invalid glyph 'x'"),
+            ("glowing", "unknown option 'glowing'"),
+        ];
+        for (options, expected) in cases {
+            let error = LegendEntry::parse('x', options).unwrap_err();
+            assert_eq!(error, expected, "{:?}", options);
+        }
+    }
+
+    #[test]
+    fn tilesets_describe_their_grid() {
+        let tileset = parse_tileset(
+            "forest",
+            "image forest.png, tile 16 16, columns 34, rows 32, padding 1 1, offset 2 2, first 256",
+        )
+        .unwrap();
+        assert_eq!(
+            tileset,
+            TilesetDescription {
+                name: "forest".to_string(),
+                image: "forest.png".to_string(),
+                tile_size: Vec2::splat(16.),
+                columns: 34,
+                rows: 32,
+                padding: Vec2::ONE,
+                offset: Vec2::splat(2.),
+                first_gid: 256,
+            }
+        );
+
+        let cases = [
+            ("image a.png, tile 16", "expected two numbers, found '16'"),
+            ("image a.png, tile 16 x", "invalid number 'x'"),
+            ("image a.png, tile 16 16, columns -1", "invalid number '-1'"),
+            (
+                "image a.png, tile 16 16, scale 2",
+                "unknown option 'scale 2'",
+            ),
+            (
+                "tile 16 16, columns 1, rows 1",
+                "a tileset needs at least an image and a tile size",
+            ),
+            (
+                "image a.png, tile 16 16",
+                "a tileset needs at least one column and row",
+            ),
+        ];
+        for (options, expected) in cases {
+            assert_eq!(
+                parse_tileset("a", options).unwrap_err(),
+                expected,
+                "{:?}",
+                options
+            );
+        }
+    }
+
+    #[test]
+    fn the_ascii_sheet_can_be_redrawn_on_another_grid() {
+        let tileset =
+            parse_tileset(ASCII_TILESET, "image big.png, tile 16 16, padding 0 0").unwrap();
+        assert_eq!(
+            tileset,
+            TilesetDescription {
+                image: "big.png".to_string(),
+                tile_size: Vec2::splat(16.),
+                padding: Vec2::ZERO,
+                ..ascii_tileset_description()
+            }
+        );
+
+        let file = parse_ascii_map("[tilesets]\nascii = tile 8 8\n[map]\n#").unwrap();
+        assert_eq!(file.tilesets.len(), 1);
+        assert_eq!(file.tilesets[0].tile_size, Vec2::splat(8.));
+
+        for options in ["first 1", "columns 8"] {
+            assert_eq!(
+                parse_tileset(ASCII_TILESET, options).unwrap_err(),
+                "the ascii tileset must start at GID 0 and hold 256 glyphs",
+                "{:?}",
+                options
+            );
+        }
+    }
+
+    #[test]
+    fn map_sections_are_read_in_order() {
+        let file = parse_ascii_map(
+            "[tilesets]\n\
+             forest = image forest.png, tile 16 16, columns 34, rows 32, first 256\n\
+             [legend]\n\
+             # = glyph 35, solid\n\
+             = = glyph 205, solid\n\
+             \n\
+             [map]\n\
+             #==#\n\
+             #  #",
+        )
+        .unwrap();
+        assert_eq!(file.tilesets.len(), 2);
+        assert_eq!(file.tilesets[0], ascii_tileset_description());
+        assert_eq!(file.tilesets[1].name, "forest");
+        assert_eq!(file.tilesets[1].first_gid, 256);
+        assert_eq!(file.legend.entry('=').glyph, Some(205));
+        assert!(file.legend.entry('=').solid);
+        assert!(file.legend.entry('#').solid);
+        assert_eq!(file.rows, vec!["#==#", "#  #"]);
+    }
+
+    #[test]
+    fn maps_without_a_header_use_the_default_legend() {
+        let file = parse_ascii_map("###\n#.#\n###").unwrap();
+        assert_eq!(file.tilesets, vec![ascii_tileset_description()]);
+        assert_eq!(file.rows, vec!["###", "#.#", "###"]);
+        let wall = file.legend.entry('#');
+        assert!(wall.solid);
+        assert_eq!(wall.terrain, Some(Terrain::Wall));
+        assert!(!file.legend.entry('.').solid);
+        assert_eq!(file.legend.entry('.').glyph, Some('.' as usize));
+        assert_eq!(file.legend.entry(' ').glyph, None);
+    }
+
+    #[test]
+    fn malformed_maps_report_the_line() {
+        let cases = [
+            (
+                "[legend]\n# = solid\n[walls]",
+                "invalid map: line 3: unknown section [walls]",
+            ),
+            (
+                "[legend]\n\n# solid\n[map]",
+                "invalid map: line 3: expected '<key> = <options>'",
+            ),
+            (
+                "[legend]\n#x = solid\n[map]",
+                "invalid map: line 2: expected '# = <options>'",
+            ),
+            (
+                "[legend]\n# = solid, heavy\n[map]",
+                "invalid map: line 2: unknown option 'heavy'",
+            ),
+            (
+                "[tilesets]\nforest = image forest.png\n[map]",
+                "invalid map: line 2: a tileset needs at least an image and a tile size",
+            ),
+            (
+                "[legend]\n# = solid",
+                "invalid map: map file has no [map] section",
+            ),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(error(text), expected, "{:?}", text);
+        }
+    }
 }
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::tilemap::{Tile, TileMap};
+
+// Sides of a tile in an autotile bitmask, north is the row above in the map file
+pub const NORTH: usize = 1;
+pub const EAST: usize = 2;
+pub const SOUTH: usize = 4;
+pub const WEST: usize = 8;
+
+// Terrains whose tiles are picked from their neighbours instead of being placed one by one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Terrain {
+    Wall,
+    Water,
+    Cliff,
+}
+
+impl Terrain {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "wall" => Some(Terrain::Wall),
+            "water" => Some(Terrain::Water),
+            "cliff" => Some(Terrain::Cliff),
+            _ => None,
+        }
+    }
+}
+
+// Tile to draw for each combination of neighbours with the same terrain, indexed by the
+// bitmask of connected sides. Combinations without a variant leave the tile as it is.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AutotileRule {
+    pub variants: [Option<usize>; 16],
+}
+
+impl AutotileRule {
+    pub fn from_variants(variants: [usize; 16]) -> Self {
+        AutotileRule {
+            variants: variants.map(Some),
+        }
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.variants.contains(&Some(index))
+    }
+}
+
+// Box drawing rules for the ASCII sheet, which follows the code page 437 layout.
+// Walls use the double line characters and cliffs the single line ones.
+pub fn ascii_autotiles() -> HashMap<Terrain, AutotileRule> {
+    HashMap::from([
+        (
+            Terrain::Wall,
+            AutotileRule::from_variants([
+                254, 186, 205, 200, 186, 186, 201, 204, 205, 188, 205, 202, 187, 185, 203, 206,
+            ]),
+        ),
+        (
+            Terrain::Cliff,
+            AutotileRule::from_variants([
+                254, 179, 196, 192, 179, 179, 218, 195, 196, 217, 196, 193, 191, 180, 194, 197,
+            ]),
+        ),
+    ])
+}
+
+impl TileMap {
+    // Pick the variant of every terrain tile in the map. Loaders call this once the map is filled in.
+    pub fn autotile(&mut self) {
+        for layer in 0..self.layers.len() {
+            self.autotile_layer(layer);
+        }
+    }
+
+    // Pick the variant of every terrain tile in one layer
+    pub fn autotile_layer(&mut self, layer: usize) {
+        for index in 0..self.layers[layer].tiles.len() {
+            self.autotile_tile(layer, self.index_to_tile(index));
+        }
+    }
+
+    // Re-evaluate a tile and its neighbours after the tile changed, returning the tiles
+    // whose variant changed
+    pub fn autotile_around(&mut self, tile: IVec2) -> Vec<IVec2> {
+        let mut changed: Vec<IVec2> = Vec::new();
+        for layer in 0..self.layers.len() {
+            for offset in [IVec2::ZERO, IVec2::NEG_Y, IVec2::X, IVec2::Y, IVec2::NEG_X] {
+                if self.contains(tile + offset) && self.autotile_tile(layer, tile + offset) {
+                    changed.push(tile + offset);
+                }
+            }
+        }
+        changed
+    }
+
+    // Returns whether the tile was replaced by another variant
+    fn autotile_tile(&mut self, layer: usize, tile_pos: IVec2) -> bool {
+        let index = self.tile_index(tile_pos);
+        let Some(tile) = self.layers[layer].tiles[index] else {
+            return false;
+        };
+        let Some(terrain) = tile.terrain else {
+            return false;
+        };
+        let Some(rule) = self.tilesets[tile.tileset].autotiles.get(&terrain) else {
+            return false;
+        };
+        let Some(variant) = rule.variants[self.terrain_mask(layer, tile_pos, terrain)] else {
+            return false;
+        };
+
+        // Variants are drawn for their own orientation, so flips from the map no longer apply
+        let autotiled = Tile {
+            index: variant,
+            flip_x: false,
+            flip_y: false,
+            rotation: 0.,
+            ..tile
+        };
+        self.layers[layer].tiles[index] = Some(autotiled);
+        autotiled != tile
+    }
+
+    // Sides of a tile that connect to the same terrain in its layer. Cells outside the map
+    // never connect, so walls along the edge of a map close it off.
+    fn terrain_mask(&self, layer: usize, tile: IVec2, terrain: Terrain) -> usize {
+        [
+            (IVec2::NEG_Y, NORTH),
+            (IVec2::X, EAST),
+            (IVec2::Y, SOUTH),
+            (IVec2::NEG_X, WEST),
+        ]
+        .into_iter()
+        .filter(|(offset, _)| {
+            let neighbour = tile + *offset;
+            self.contains(neighbour)
+                && self.layers[layer].tiles[self.tile_index(neighbour)]
+                    .is_some_and(|tile| tile.terrain == Some(terrain))
+        })
+        .fold(0, |mask, (_, side)| mask | side)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tilemap::{Tile, TileLayer, Tileset};
+
+    // A map of the ASCII sheet with a wall wherever a row has a '#' and water for '~'
+    fn map(rows: &[&str]) -> TileMap {
+        let mut map = TileMap::new(rows[0].len() as u32, rows.len() as u32, Vec2::splat(16.));
+        map.tilesets.push(Tileset {
+            name: "ascii".to_string(),
+            texture: Handle::default(),
+            layout: Handle::default(),
+            animations: HashMap::new(),
+            autotiles: ascii_autotiles(),
+            traction: HashMap::new(),
+        });
+        let tiles = rows
+            .iter()
+            .flat_map(|row| row.chars())
+            .map(|char| {
+                let terrain = match char {
+                    '#' => Terrain::Wall,
+                    '~' => Terrain::Water,
+                    _ => return None,
+                };
+                Some(Tile {
+                    flip_x: true,
+                    terrain: Some(terrain),
+                    ..Tile::new(0, char as usize)
+                })
+            })
+            .collect();
+        map.layers.push(TileLayer {
+            name: "walls".to_string(),
+            tiles,
+            visible: true,
+            opacity: 1.,
+        });
+        map
+    }
+
+    // Glyph of every cell by row, 0 for empty cells
+    fn glyphs(map: &TileMap) -> Vec<Vec<usize>> {
+        map.layers[0]
+            .tiles
+            .chunks(map.width as usize)
+            .map(|row| {
+                row.iter()
+                    .map(|tile| tile.map_or(0, |tile| tile.index))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn walls_join_the_neighbours_with_the_same_terrain() {
+        let mut map = map(&["###", "# #", "###", "#~#"]);
+        map.autotile();
+        assert_eq!(
+            glyphs(&map),
+            [
+                [201, 205, 187], // ╔═╗
+                [186, 0, 186],   // ║ ║
+                [204, 205, 185], // ╠═╣
+                [186, 126, 186], // ║~║
+            ]
+        );
+
+        // Variants are drawn upright, water has no ASCII rule and keeps its tile
+        let tiles = &map.layers[0].tiles;
+        assert!(!tiles[0].unwrap().flip_x);
+        assert!(tiles[10].unwrap().flip_x);
+    }
+
+    #[test]
+    fn changed_tiles_update_their_neighbours() {
+        let mut map = map(&["###", "# #", "###"]);
+        map.autotile();
+        let wall = map.layers[0].tiles[0].unwrap();
+        map.set_tile(0, IVec2::new(1, 1), wall);
+        assert_eq!(map.take_changed_tiles().len(), 5);
+        assert_eq!(
+            glyphs(&map),
+            [
+                [201, 203, 187], // ╔╦╗
+                [204, 206, 185], // ╠╬╣
+                [200, 202, 188], // ╚╩╝
+            ]
+        );
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+
+use crate::components::{
+    AnimatedTile, AnimatedTileMesh, CollisionLayers, MapRoot, TileChunk, TileCollider,
+};
+use crate::constants::{CHUNK_LOAD_DISTANCE, CHUNK_SIZE, CHUNK_UNLOAD_DISTANCE, TILE_LAYER_Z};
+use crate::tile_mesh::{TileMeshAssets, TileRenderMode};
+use crate::tilemap::{TileChanged, TileMap};
+use bevy::{prelude::*, sprite::MaterialMesh2dBundle};
+
+// Chunks of the current map that are spawned, by chunk coordinate
+#[derive(Resource, Default)]
+pub struct LoadedChunks(pub HashMap<IVec2, Entity>);
+
+// Collider entities of the current map, by tile. Unlike the art they exist for the whole map,
+// so movers far from the camera still hit walls. Enclosed solid cells have none.
+#[derive(Resource, Default)]
+pub struct MapColliders(pub HashMap<IVec2, Entity>);
+
+// Spawn the collider of a solid tile, solidity is independent of the art
+pub fn spawn_tile_collider(
+    commands: &mut Commands,
+    map: &TileMap,
+    tile_pos: IVec2,
+) -> Option<Entity> {
+    if map.is_enclosed(tile_pos) {
+        return None;
+    }
+    let collider = map.collider(tile_pos)?;
+    let entity = commands
+        .spawn(SpatialBundle::from_transform(Transform::from_translation(
+            map.tile_to_world(tile_pos).extend(0.),
+        )))
+        .insert((
+            TileCollider,
+            collider,
+            CollisionLayers::on([map.collision_layer(tile_pos)]),
+        ))
+        .id();
+    Some(entity)
+}
+
+// Spawn the tiles of one chunk as children of a single chunk entity
+pub fn spawn_chunk(
+    commands: &mut Commands,
+    map: &TileMap,
+    chunk: IVec2,
+    mesh_assets: &mut TileMeshAssets,
+) -> Entity {
+    let tiles = match *mesh_assets.render_mode {
+        TileRenderMode::Batched => spawn_chunk_meshes(commands, map, chunk, mesh_assets),
+        TileRenderMode::Sprites => {
+            spawn_chunk_sprites(commands, map, chunk, mesh_assets.animation_time())
+        }
+    };
+
+    commands
+        .spawn(SpatialBundle::default())
+        .insert(Name::new(format!("Chunk {} {}", chunk.x, chunk.y)))
+        .insert(TileChunk(chunk))
+        .push_children(&tiles)
+        .id()
+}
+
+// Draw each layer of the chunk with one mesh per tileset it uses.
+// Each layer gets its own z so later layers draw on top of earlier ones.
+fn spawn_chunk_meshes(
+    commands: &mut Commands,
+    map: &TileMap,
+    chunk: IVec2,
+    mesh_assets: &mut TileMeshAssets,
+) -> Vec<Entity> {
+    let mut meshes: Vec<Entity> = Vec::new();
+
+    for (z, layer) in map.layers.iter().enumerate() {
+        if !layer.visible {
+            continue;
+        }
+        for (i, tileset) in map.tilesets.iter().enumerate() {
+            let Some(mesh) = mesh_assets.layer_mesh(map, layer, i, map.chunk_tiles(chunk)) else {
+                continue;
+            };
+            let mut entity = commands.spawn(MaterialMesh2dBundle {
+                mesh: mesh.into(),
+                material: mesh_assets.material(&tileset.texture),
+                transform: Transform::from_xyz(0., 0., TILE_LAYER_Z + z as f32),
+                ..default()
+            });
+            let animated = map.chunk_tiles(chunk).any(|tile_pos| {
+                layer.tiles[map.tile_index(tile_pos)]
+                    .is_some_and(|tile| tile.tileset == i && map.is_animated(&tile))
+            });
+            if animated {
+                entity.insert(AnimatedTileMesh {
+                    chunk,
+                    layer: z,
+                    tileset: i,
+                });
+            }
+            meshes.push(entity.id());
+        }
+    }
+
+    meshes
+}
+
+// Draw every tile of the chunk as its own sprite, handy for debugging
+fn spawn_chunk_sprites(
+    commands: &mut Commands,
+    map: &TileMap,
+    chunk: IVec2,
+    time: f32,
+) -> Vec<Entity> {
+    let mut sprites: Vec<Entity> = Vec::new();
+
+    for (z, layer) in map.layers.iter().enumerate() {
+        if !layer.visible {
+            continue;
+        }
+        for tile_pos in map.chunk_tiles(chunk) {
+            let Some(tile) = layer.tiles[map.tile_index(tile_pos)] else {
+                continue;
+            };
+            let tileset = &map.tilesets[tile.tileset];
+
+            let mut entity = commands.spawn(SpriteSheetBundle {
+                sprite: Sprite {
+                    flip_x: tile.flip_x,
+                    flip_y: tile.flip_y,
+                    custom_size: Some(map.grid.tile_size),
+                    color: tile.color.with_a(tile.color.a() * layer.opacity),
+                    ..default()
+                },
+                texture: tileset.texture.clone(),
+                atlas: TextureAtlas {
+                    index: tileset.frame(tile.index, time),
+                    layout: tileset.layout.clone(),
+                },
+                transform: Transform {
+                    translation: map.tile_to_world(tile_pos).extend(TILE_LAYER_Z + z as f32),
+                    rotation: Quat::from_rotation_z(tile.rotation),
+                    ..default()
+                },
+                ..default()
+            });
+            if tileset.is_animated(tile.index) {
+                entity.insert(AnimatedTile {
+                    tileset: tile.tileset,
+                    index: tile.index,
+                });
+            }
+            sprites.push(entity.id());
+        }
+    }
+
+    sprites
+}
+
+// Respawn the colliders of tiles that were changed through the TileMap resource, and the
+// loaded chunks containing them. Their neighbours may have become enclosed or exposed.
+pub fn refresh_changed_chunks_system(
+    mut commands: Commands,
+    map: Option<ResMut<TileMap>>,
+    loaded_chunks: Option<ResMut<LoadedChunks>>,
+    colliders: Option<ResMut<MapColliders>>,
+    mut mesh_assets: TileMeshAssets,
+    root_query: Query<Entity, With<MapRoot>>,
+    mut events: EventWriter<TileChanged>,
+) {
+    let (Some(mut map), Some(mut loaded_chunks), Some(mut colliders)) =
+        (map, loaded_chunks, colliders)
+    else {
+        return;
+    };
+    if !map.has_changed_tiles() {
+        return;
+    }
+    let changed = map.take_changed_tiles();
+
+    let chunks: HashSet<IVec2> = changed
+        .iter()
+        .map(|tile| map.tile_to_chunk(*tile))
+        .collect();
+    if let Ok(root) = root_query.get_single() {
+        for tile in changed.iter() {
+            if let Some(entity) = colliders.0.remove(tile) {
+                commands.entity(entity).despawn_recursive();
+            }
+            if let Some(entity) = spawn_tile_collider(&mut commands, &map, *tile) {
+                commands.entity(root).add_child(entity);
+                colliders.0.insert(*tile, entity);
+            }
+        }
+        let neighbours: HashSet<IVec2> = changed
+            .iter()
+            .flat_map(|tile| [IVec2::NEG_Y, IVec2::X, IVec2::Y, IVec2::NEG_X].map(|o| *tile + o))
+            .filter(|tile| !changed.contains(tile))
+            .collect();
+        for tile in neighbours {
+            if map.is_enclosed(tile) {
+                if let Some(entity) = colliders.0.remove(&tile) {
+                    commands.entity(entity).despawn_recursive();
+                }
+            } else if let Entry::Vacant(entry) = colliders.0.entry(tile) {
+                if let Some(entity) = spawn_tile_collider(&mut commands, &map, tile) {
+                    commands.entity(root).add_child(entity);
+                    entry.insert(entity);
+                }
+            }
+        }
+        for chunk in chunks {
+            // Chunks that are not loaded pick up the changes when they are spawned
+            let Some(entity) = loaded_chunks.0.get_mut(&chunk) else {
+                continue;
+            };
+            commands.entity(*entity).despawn_recursive();
+            *entity = spawn_chunk(&mut commands, &map, chunk, &mut mesh_assets);
+            commands.entity(root).add_child(*entity);
+        }
+    }
+
+    events.send_batch(changed.into_iter().map(|tile| TileChanged { tile }));
+}
+
+// Spawn chunks that come within range of the camera and despawn those that leave it.
+// Chunks unload a bit further out than they load so they don't flicker at the edge.
+pub fn chunk_streaming_system(
+    mut commands: Commands,
+    map: Option<Res<TileMap>>,
+    loaded_chunks: Option<ResMut<LoadedChunks>>,
+    mut mesh_assets: TileMeshAssets,
+    camera_query: Query<&Transform, With<Camera2d>>,
+    root_query: Query<Entity, With<MapRoot>>,
+) {
+    let (Some(map), Some(mut loaded_chunks)) = (map, loaded_chunks) else {
+        return;
+    };
+    let (Ok(camera_tf), Ok(root)) = (camera_query.get_single(), root_query.get_single()) else {
+        return;
+    };
+    let camera = camera_tf.translation.truncate();
+
+    // Switching render modes respawns every chunk with the new mode
+    let respawn = mesh_assets.render_mode.is_changed();
+    loaded_chunks.0.retain(|chunk, entity| {
+        if !respawn && map.chunk_distance(*chunk, camera) <= CHUNK_UNLOAD_DISTANCE {
+            return true;
+        }
+        commands.entity(*entity).despawn_recursive();
+        false
+    });
+
+    // Only look at chunks that could be in range instead of every chunk in the map
+    let count = map.chunk_count();
+    let center = map.tile_to_chunk(map.grid.world_to_tile(camera));
+    let chunk_size = map.grid.tile_size.min_element() * CHUNK_SIZE as f32;
+    let reach = (CHUNK_LOAD_DISTANCE / chunk_size).ceil() as i32 + 1;
+    for y in (center.y - reach).max(0)..(center.y + reach + 1).min(count.y) {
+        for x in (center.x - reach).max(0)..(center.x + reach + 1).min(count.x) {
+            let chunk = IVec2::new(x, y);
+            if loaded_chunks.0.contains_key(&chunk)
+                || map.chunk_distance(chunk, camera) > CHUNK_LOAD_DISTANCE
+            {
+                continue;
+            }
+            let entity = spawn_chunk(&mut commands, &map, chunk, &mut mesh_assets);
+            commands.entity(root).add_child(entity);
+            loaded_chunks.0.insert(chunk, entity);
+        }
+    }
+}
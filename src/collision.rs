@@ -0,0 +1,1008 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{
+    ecs::system::SystemParam,
+    math::bounding::{Aabb2d, BoundingCircle, BoundingVolume},
+    prelude::*,
+};
+
+use crate::components::{Collider, ColliderShape, CollisionLayers, Layer, Player, TileCollider};
+use crate::tilemap::{MapGrid, TileMap};
+
+pub struct CollisionPlugin;
+
+impl Plugin for CollisionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ColliderGrid>()
+            .init_resource::<Collisions>()
+            .add_event::<CollisionStarted>()
+            .add_event::<CollisionEnded>()
+            // Colliders spawned or despawned last frame are indexed before anything moves
+            .add_systems(PreUpdate, collider_grid_system)
+            // Runs after every fixed step, once everything has moved
+            .add_systems(FixedPostUpdate, collision_events_system);
+    }
+}
+
+// Tile colliders by the tiles they cover, so movers only test the walls around them.
+// Tile colliders never move, they are indexed once when they are added.
+#[derive(Resource, Default)]
+pub struct ColliderGrid {
+    cells: HashMap<IVec2, Vec<Entity>>,
+    entities: HashMap<Entity, Vec<IVec2>>, // Cells each collider was added to
+}
+
+impl ColliderGrid {
+    pub fn insert(&mut self, entity: Entity, cells: Vec<IVec2>) {
+        self.remove(entity);
+        for cell in cells.iter() {
+            self.cells.entry(*cell).or_default().push(entity);
+        }
+        self.entities.insert(entity, cells);
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        let Some(cells) = self.entities.remove(&entity) else {
+            return;
+        };
+        for cell in cells {
+            if let Some(entities) = self.cells.get_mut(&cell) {
+                entities.retain(|other| *other != entity);
+                if entities.is_empty() {
+                    self.cells.remove(&cell);
+                }
+            }
+        }
+    }
+
+    // Colliders in the tiles touched by an area, each returned once
+    pub fn query(&self, grid: &MapGrid, area: Aabb2d) -> Vec<Entity> {
+        let mut entities: Vec<Entity> = covered_tiles(grid, area)
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+            .collect();
+        entities.sort_unstable();
+        entities.dedup();
+        entities
+    }
+}
+
+// Every tile an area overlaps, including tiles outside the map
+pub fn covered_tiles(grid: &MapGrid, area: Aabb2d) -> impl Iterator<Item = IVec2> {
+    let a = grid.world_to_tile(area.min);
+    let b = grid.world_to_tile(area.max);
+    let (min, max) = (a.min(b), a.max(b));
+    (min.y..=max.y).flat_map(move |y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+}
+
+// Keep the grid in step with the TileCollider components in the world
+fn collider_grid_system(
+    mut grid: ResMut<ColliderGrid>,
+    map: Option<Res<TileMap>>,
+    mut removed: RemovedComponents<TileCollider>,
+    added_query: Query<(Entity, &Transform, &Collider), Added<TileCollider>>,
+) {
+    for entity in removed.read() {
+        grid.remove(entity);
+    }
+
+    // Colliders only come from maps, so there always is one when they are added
+    let Some(map) = map else {
+        return;
+    };
+    for (entity, transform, collider) in added_query.iter() {
+        let area = collider.at(transform.translation.truncate()).aabb();
+        grid.insert(entity, covered_tiles(&map.grid, area).collect());
+    }
+}
+
+// A collider overlapping a box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Contact {
+    pub entity: Entity,
+    pub penetration: f32, // How far the box reaches into the collider along the normal
+    pub normal: Vec2,     // Direction that pushes the box out of the collider the shortest way
+}
+
+// A collider placed in the world
+#[derive(Debug, Clone, Copy)]
+pub enum Bounds {
+    Aabb(Aabb2d),
+    Circle(BoundingCircle),
+}
+
+impl Collider {
+    pub fn aabb(half_extents: Vec2) -> Self {
+        Collider {
+            shape: ColliderShape::Aabb { half_extents },
+            offset: Vec2::ZERO,
+        }
+    }
+
+    pub fn circle(radius: f32) -> Self {
+        Collider {
+            shape: ColliderShape::Circle { radius },
+            offset: Vec2::ZERO,
+        }
+    }
+
+    pub fn with_offset(self, offset: Vec2) -> Self {
+        Collider { offset, ..self }
+    }
+
+    // The collider of an entity at a world position
+    pub fn at(&self, position: Vec2) -> Bounds {
+        let center = position + self.offset;
+        match self.shape {
+            ColliderShape::Aabb { half_extents } => Bounds::Aabb(Aabb2d::new(center, half_extents)),
+            ColliderShape::Circle { radius } => Bounds::Circle(BoundingCircle::new(center, radius)),
+        }
+    }
+}
+
+impl Layer {
+    pub fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+impl Default for CollisionLayers {
+    fn default() -> Self {
+        CollisionLayers::ALL
+    }
+}
+
+impl CollisionLayers {
+    pub const ALL: CollisionLayers = CollisionLayers {
+        memberships: u32::MAX,
+        filters: u32::MAX,
+    };
+
+    pub fn new(
+        memberships: impl IntoIterator<Item = Layer>,
+        filters: impl IntoIterator<Item = Layer>,
+    ) -> Self {
+        CollisionLayers {
+            memberships: layer_mask(memberships),
+            filters: layer_mask(filters),
+        }
+    }
+
+    // A collider on the given layers that collides with everything
+    pub fn on(memberships: impl IntoIterator<Item = Layer>) -> Self {
+        CollisionLayers {
+            filters: u32::MAX,
+            ..CollisionLayers::new(memberships, [])
+        }
+    }
+
+    pub fn interacts(&self, other: &CollisionLayers) -> bool {
+        self.filters & other.memberships != 0 && other.filters & self.memberships != 0
+    }
+}
+
+fn layer_mask(layers: impl IntoIterator<Item = Layer>) -> u32 {
+    layers.into_iter().fold(0, |mask, layer| mask | layer.bit())
+}
+
+impl Bounds {
+    // Smallest box around the collider, used to find the grid cells it covers
+    pub fn aabb(&self) -> Aabb2d {
+        match self {
+            Bounds::Aabb(aabb) => *aabb,
+            Bounds::Circle(circle) => circle.aabb_2d(),
+        }
+    }
+
+    // Penetration and normal pushing this collider out of another, None if they don't overlap.
+    // Colliders that only touch don't count as overlapping.
+    pub fn contact(&self, other: &Bounds) -> Option<(f32, Vec2)> {
+        match (self, other) {
+            (Bounds::Aabb(a), Bounds::Aabb(b)) => aabb_contact(*a, *b),
+            (Bounds::Circle(a), Bounds::Circle(b)) => circle_contact(*a, *b),
+            (Bounds::Circle(a), Bounds::Aabb(b)) => circle_aabb_contact(*a, *b),
+            (Bounds::Aabb(a), Bounds::Circle(b)) => {
+                circle_aabb_contact(*b, *a).map(|(penetration, normal)| (penetration, -normal))
+            }
+        }
+    }
+
+    pub fn intersects(&self, other: &Bounds) -> bool {
+        self.contact(other).is_some()
+    }
+}
+
+// Boxes are pushed out along the axis they overlap least on
+pub fn aabb_contact(moving: Aabb2d, other: Aabb2d) -> Option<(f32, Vec2)> {
+    let overlap = moving.max.min(other.max) - moving.min.max(other.min);
+    if overlap.x <= 0. || overlap.y <= 0. {
+        return None;
+    }
+
+    let offset = moving.center() - other.center();
+    if overlap.x < overlap.y {
+        let normal = if offset.x < 0. { Vec2::NEG_X } else { Vec2::X };
+        Some((overlap.x, normal))
+    } else {
+        let normal = if offset.y < 0. { Vec2::NEG_Y } else { Vec2::Y };
+        Some((overlap.y, normal))
+    }
+}
+
+// When a box moving by delta first touches another box, as a fraction of the move, and the
+// normal of the face it touches. Boxes that already overlap are free to move apart, and
+// boxes sliding exactly along a face never touch it.
+pub fn sweep_aabb(moving: Aabb2d, delta: Vec2, other: Aabb2d) -> Option<(f32, Vec2)> {
+    let mut entry = Vec2::NEG_INFINITY;
+    let mut exit = Vec2::INFINITY;
+    for axis in 0..2 {
+        let (min, max) = (moving.min[axis], moving.max[axis]);
+        let (other_min, other_max) = (other.min[axis], other.max[axis]);
+        let d = delta[axis];
+        if d > 0. {
+            entry[axis] = (other_min - max) / d;
+            exit[axis] = (other_max - min) / d;
+        } else if d < 0. {
+            entry[axis] = (other_max - min) / d;
+            exit[axis] = (other_min - max) / d;
+        } else if max <= other_min + SWEEP_EPSILON || min >= other_max - SWEEP_EPSILON {
+            return None;
+        }
+    }
+
+    let axis = if entry.x > entry.y { 0 } else { 1 };
+    let (time, exit) = (entry[axis], exit.min_element());
+    if time >= exit || time > 1. || exit <= 0. {
+        return None;
+    }
+    // Rounding can leave a box resting against a wall a hair inside it
+    if time * delta[axis].abs() < -SWEEP_EPSILON {
+        return None;
+    }
+
+    let mut normal = Vec2::ZERO;
+    normal[axis] = -delta[axis].signum();
+    Some((time.max(0.), normal))
+}
+
+fn circle_contact(moving: BoundingCircle, other: BoundingCircle) -> Option<(f32, Vec2)> {
+    let offset = moving.center - other.center;
+    let penetration = moving.radius() + other.radius() - offset.length();
+    if penetration <= 0. {
+        return None;
+    }
+    // Circles on the same spot have no shortest way out, push them up
+    Some((penetration, offset.try_normalize().unwrap_or(Vec2::Y)))
+}
+
+fn circle_aabb_contact(moving: BoundingCircle, other: Aabb2d) -> Option<(f32, Vec2)> {
+    let offset = moving.center - other.closest_point(moving.center);
+    if offset == Vec2::ZERO {
+        // The center is inside the box, push it out like a box of the same size
+        return aabb_contact(moving.aabb_2d(), other);
+    }
+    let penetration = moving.radius() - offset.length();
+    if penetration <= 0. {
+        return None;
+    }
+    Some((penetration, offset.normalize()))
+}
+
+// Tile colliders with the grid to find the ones near an area
+#[derive(SystemParam)]
+#[allow(clippy::type_complexity)]
+pub struct TileColliders<'w, 's> {
+    grid: Res<'w, ColliderGrid>,
+    map: Option<Res<'w, TileMap>>,
+    query: Query<
+        'w,
+        's,
+        (
+            &'static Transform,
+            &'static Collider,
+            Option<&'static CollisionLayers>,
+        ),
+        (With<TileCollider>, Without<Player>),
+    >,
+}
+
+impl TileColliders<'_, '_> {
+    // Colliders in the tiles an area overlaps, they may still be outside the area itself
+    pub fn near(
+        &self,
+        area: Aabb2d,
+    ) -> impl Iterator<Item = (Entity, &Transform, &Collider, &CollisionLayers)> {
+        let entities = match &self.map {
+            Some(map) => self.grid.query(&map.grid, area),
+            None => Vec::new(),
+        };
+        entities.into_iter().filter_map(|entity| {
+            let (transform, collider, layers) = self.query.get(entity).ok()?;
+            Some((
+                entity,
+                transform,
+                collider,
+                layers.unwrap_or(&CollisionLayers::ALL),
+            ))
+        })
+    }
+
+    // Every tile collider on the given layers' filters overlapping a collider, deepest first
+    pub fn contacts(&self, bounds: Bounds, layers: &CollisionLayers) -> Vec<Contact> {
+        let mut contacts: Vec<Contact> = self
+            .near(bounds.aabb())
+            .filter(|(_, _, _, wall_layers)| layers.interacts(wall_layers))
+            .filter_map(|(entity, transform, collider, _)| {
+                let wall = collider.at(transform.translation.truncate());
+                let (penetration, normal) = bounds.contact(&wall)?;
+                Some(Contact {
+                    entity,
+                    penetration,
+                    normal,
+                })
+            })
+            .collect();
+        contacts.sort_by(|a, b| b.penetration.total_cmp(&a.penetration));
+        contacts
+    }
+
+    // Move a collider by delta, stopping flush against the walls in the way and sliding
+    // along them with what is left of the move. Colliders are swept as their bounding box,
+    // so moves longer than a tile can't skip over thin walls. Returns the new position and
+    // the walls that were hit.
+    pub fn move_and_slide(
+        &self,
+        position: Vec2,
+        delta: Vec2,
+        collider: &Collider,
+        layers: &CollisionLayers,
+    ) -> (Vec2, Vec<Contact>) {
+        let mut position = position;
+        let mut delta = delta;
+        let mut hits: Vec<Contact> = Vec::new();
+
+        for _ in 0..MAX_SLIDES {
+            if delta == Vec2::ZERO {
+                break;
+            }
+            let Some((time, contact)) = self.sweep(position, delta, collider, layers) else {
+                position += delta;
+                break;
+            };
+            position += delta * time;
+            hits.push(contact);
+
+            // Only the part of the move along the wall is left
+            let remaining = delta * (1. - time);
+            delta = remaining - contact.normal * remaining.dot(contact.normal);
+        }
+
+        (position, hits)
+    }
+
+    // Move a collider by delta, stopping flush against the first wall in the way without
+    // sliding along it. Returns the new position and the wall that was hit, if any.
+    pub fn move_until_hit(
+        &self,
+        position: Vec2,
+        delta: Vec2,
+        collider: &Collider,
+        layers: &CollisionLayers,
+    ) -> (Vec2, Option<Contact>) {
+        if delta == Vec2::ZERO {
+            return (position, None);
+        }
+        match self.sweep(position, delta, collider, layers) {
+            Some((time, contact)) => (position + delta * time, Some(contact)),
+            None => (position + delta, None),
+        }
+    }
+
+    // The first wall a collider's bounding box runs into over a move, and the fraction of
+    // the move made before touching it
+    fn sweep(
+        &self,
+        position: Vec2,
+        delta: Vec2,
+        collider: &Collider,
+        layers: &CollisionLayers,
+    ) -> Option<(f32, Contact)> {
+        let start = collider.at(position).aabb();
+        let end = Aabb2d {
+            min: start.min + delta,
+            max: start.max + delta,
+        };
+        self.near(start.merge(&end))
+            .filter(|(_, _, _, wall_layers)| layers.interacts(wall_layers))
+            .filter_map(|(entity, transform, wall, _)| {
+                let wall = wall.at(transform.translation.truncate()).aabb();
+                let (time, normal) = sweep_aabb(start, delta, wall)?;
+                let contact = Contact {
+                    entity,
+                    penetration: 0.,
+                    normal,
+                };
+                Some((time, contact))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    }
+}
+
+// Gaps smaller than this count as touching when sweeping
+const SWEEP_EPSILON: f32 = 1e-3;
+
+// Slides a move can be split into before the rest of it is dropped
+const MAX_SLIDES: usize = 4;
+
+// Two colliding entities, the one with the lower id first so each pair has one order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CollisionPair(Entity, Entity);
+
+impl CollisionPair {
+    pub fn new(a: Entity, b: Entity) -> Self {
+        if a < b {
+            CollisionPair(a, b)
+        } else {
+            CollisionPair(b, a)
+        }
+    }
+
+    pub fn entities(&self) -> (Entity, Entity) {
+        (self.0, self.1)
+    }
+
+    // The entity colliding with the given one, None if it is not part of the pair
+    pub fn other(&self, entity: Entity) -> Option<Entity> {
+        match *self {
+            CollisionPair(a, b) if a == entity => Some(b),
+            CollisionPair(a, b) if b == entity => Some(a),
+            _ => None,
+        }
+    }
+}
+
+// Sent the first frame two colliders touch
+#[derive(Event, Debug, Clone, Copy, Deref)]
+pub struct CollisionStarted(pub CollisionPair);
+
+// Sent the first frame two colliders stop touching, either entity may be despawned already
+#[derive(Event, Debug, Clone, Copy, Deref)]
+pub struct CollisionEnded(pub CollisionPair);
+
+// Pairs of colliders that are touching. Movers stopped by a wall never overlap it, so
+// movement reports the walls it was blocked by.
+#[derive(Resource, Default)]
+pub struct Collisions {
+    blocked: HashSet<CollisionPair>, // Walls that blocked a mover this frame
+    touching: HashSet<CollisionPair>,
+}
+
+impl Collisions {
+    pub fn block(&mut self, mover: Entity, wall: Entity) {
+        self.blocked.insert(CollisionPair::new(mover, wall));
+    }
+
+    #[cfg(test)]
+    pub fn contains(&self, a: Entity, b: Entity) -> bool {
+        self.touching.contains(&CollisionPair::new(a, b))
+    }
+}
+
+// Find every pair of colliders touching this frame and send events for the ones that
+// started or stopped touching. Entities are tested against each other one by one, there
+// are few enough of them compared to tiles.
+fn collision_events_system(
+    mut collisions: ResMut<Collisions>,
+    query: Query<(Entity, &Transform, &Collider, Option<&CollisionLayers>), Without<TileCollider>>,
+    walls: TileColliders,
+    mut started: EventWriter<CollisionStarted>,
+    mut ended: EventWriter<CollisionEnded>,
+) {
+    let mut touching: HashSet<CollisionPair> = collisions.blocked.drain().collect();
+
+    for (entity, transform, collider, layers) in query.iter() {
+        let bounds = collider.at(transform.translation.truncate());
+        let layers = layers.unwrap_or(&CollisionLayers::ALL);
+        for contact in walls.contacts(bounds, layers) {
+            touching.insert(CollisionPair::new(entity, contact.entity));
+        }
+    }
+    for [(a, a_tf, a_collider, a_layers), (b, b_tf, b_collider, b_layers)] in
+        query.iter_combinations()
+    {
+        let (a_layers, b_layers) = (
+            a_layers.unwrap_or(&CollisionLayers::ALL),
+            b_layers.unwrap_or(&CollisionLayers::ALL),
+        );
+        if !a_layers.interacts(b_layers) {
+            continue;
+        }
+        let a_bounds = a_collider.at(a_tf.translation.truncate());
+        if a_bounds.intersects(&b_collider.at(b_tf.translation.truncate())) {
+            touching.insert(CollisionPair::new(a, b));
+        }
+    }
+
+    started.send_batch(
+        touching
+            .difference(&collisions.touching)
+            .map(|pair| CollisionStarted(*pair)),
+    );
+    ended.send_batch(
+        collisions
+            .touching
+            .difference(&touching)
+            .map(|pair| CollisionEnded(*pair)),
+    );
+    collisions.touching = touching;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::autotile::Terrain;
+    use crate::components::{Movable, Projectile, Sensor, Velocity};
+    use crate::constants::PLAYER_HALF_EXTENTS;
+    use crate::resources::WinSize;
+    use crate::tilemap::{Tile, TileLayer};
+
+    // A headless world with a 10x10 map and a wall on each of the given tiles
+    fn world_with_walls(walls: &[IVec2]) -> (World, Vec<Entity>) {
+        let mut map = TileMap::new(10, 10, Vec2::splat(32.));
+        for tile in walls {
+            map.set_solid(*tile, true);
+        }
+        world_with_map(map, walls)
+    }
+
+    // Spawn the colliders of the given solid tiles the way map chunks do
+    fn world_with_map(map: TileMap, walls: &[IVec2]) -> (World, Vec<Entity>) {
+        let mut world = World::new();
+        world.insert_resource(WinSize { w: 320., h: 320. });
+        // Every run of the movement system is one 60 Hz step
+        let mut time = Time::<()>::default();
+        time.advance_by(Duration::from_secs_f64(1. / 60.));
+        world.insert_resource(time);
+        world.init_resource::<ColliderGrid>();
+        world.init_resource::<Collisions>();
+        world.init_resource::<Events<CollisionStarted>>();
+        world.init_resource::<Events<CollisionEnded>>();
+
+        let entities = walls
+            .iter()
+            .map(|tile| {
+                world
+                    .spawn(Transform::from_translation(
+                        map.tile_to_world(*tile).extend(0.),
+                    ))
+                    .insert((
+                        TileCollider,
+                        map.collider(*tile).unwrap(),
+                        CollisionLayers::on([map.collision_layer(*tile)]),
+                    ))
+                    .id()
+            })
+            .collect();
+        world.insert_resource(map);
+        world.run_system_once(collider_grid_system);
+        (world, entities)
+    }
+
+    fn spawn_mover(world: &mut World, tile: IVec2, velocity: Vec2) -> Entity {
+        let position = world.resource::<TileMap>().tile_to_world(tile);
+        world
+            .spawn(Transform::from_translation(position.extend(1.)))
+            .insert(Collider::aabb(PLAYER_HALF_EXTENTS))
+            .insert(Velocity {
+                x: velocity.x,
+                y: velocity.y,
+            })
+            .insert(Movable {
+                auto_despawn: false,
+            })
+            .id()
+    }
+
+    fn move_for(world: &mut World, frames: usize) {
+        for _ in 0..frames {
+            world.run_system_once(crate::movable_system);
+        }
+    }
+
+    #[test]
+    fn contacts_report_every_overlapping_wall() {
+        let (mut world, walls) =
+            world_with_walls(&[IVec2::new(1, 1), IVec2::new(4, 4), IVec2::new(5, 4)]);
+
+        // A box straddling the two walls on row 4, reaching 4 pixels into them from below
+        let center = world.resource::<TileMap>().tile_to_world(IVec2::new(4, 4));
+        let area = Aabb2d::new(
+            Vec2::new(center.x + 16., center.y - 16. - 6.),
+            Vec2::new(8., 10.),
+        );
+        let mut contacts = world.run_system_once(move |walls: TileColliders| {
+            walls.contacts(Bounds::Aabb(area), &CollisionLayers::ALL)
+        });
+        contacts.sort_by_key(|contact| contact.entity);
+
+        assert_eq!(contacts.len(), 2);
+        for (contact, wall) in contacts.iter().zip(&walls[1..]) {
+            assert_eq!(contact.entity, *wall);
+            assert_eq!(contact.penetration, 4.);
+            // Rows go down the screen, so pushing the box out of row 4 back towards
+            // row 5 means moving it down
+            assert_eq!(contact.normal, Vec2::NEG_Y);
+        }
+    }
+
+    #[test]
+    fn touching_walls_are_not_contacts() {
+        let (mut world, _) = world_with_walls(&[IVec2::new(4, 4)]);
+
+        let center = world.resource::<TileMap>().tile_to_world(IVec2::new(4, 4));
+        let area = Aabb2d::new(Vec2::new(center.x + 16. + 6., center.y), Vec2::new(6., 6.));
+        let contacts = world.run_system_once(move |walls: TileColliders| {
+            walls.contacts(Bounds::Aabb(area), &CollisionLayers::ALL)
+        });
+
+        assert!(contacts.is_empty());
+    }
+
+    #[test]
+    fn walls_after_the_first_candidate_still_block_movement() {
+        // The wall behind the mover is nearer in the grid but never in the way
+        let (mut world, walls) = world_with_walls(&[IVec2::new(3, 5), IVec2::new(5, 5)]);
+        let mover = spawn_mover(&mut world, IVec2::new(4, 5), Vec2::X * 100.);
+
+        move_for(&mut world, 60);
+
+        let wall_x = world.get::<Transform>(walls[1]).unwrap().translation.x;
+        let mover_x = world.get::<Transform>(mover).unwrap().translation.x;
+        // It stops flush against the wall, not a step short of it
+        assert!((mover_x + PLAYER_HALF_EXTENTS.x - (wall_x - 16.)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn blocked_axis_does_not_stop_the_other() {
+        // Moving diagonally into a wall on the right slides along it
+        let (mut world, _) = world_with_walls(&[
+            IVec2::new(5, 3),
+            IVec2::new(5, 4),
+            IVec2::new(5, 5),
+            IVec2::new(5, 6),
+        ]);
+        let mover = spawn_mover(&mut world, IVec2::new(4, 5), Vec2::new(100., 100.));
+        let start = world.get::<Transform>(mover).unwrap().translation;
+
+        move_for(&mut world, 20);
+
+        let end = world.get::<Transform>(mover).unwrap().translation;
+        assert!(end.x - start.x < 16.);
+        assert!(end.y > start.y + 30.);
+    }
+
+    #[test]
+    fn movement_is_free_between_walls() {
+        let (mut world, _) = world_with_walls(&[
+            IVec2::new(3, 2),
+            IVec2::new(3, 3),
+            IVec2::new(3, 4),
+            IVec2::new(5, 2),
+            IVec2::new(5, 3),
+            IVec2::new(5, 4),
+        ]);
+        let mover = spawn_mover(&mut world, IVec2::new(4, 4), Vec2::Y * 100.);
+        let start = world.get::<Transform>(mover).unwrap().translation;
+
+        move_for(&mut world, 30);
+
+        let end = world.get::<Transform>(mover).unwrap().translation;
+        assert_eq!(end.x, start.x);
+        assert!(end.y > start.y + 45.);
+    }
+
+    #[test]
+    fn partial_tile_colliders_only_block_their_area() {
+        // A half wall filling the top of its tile, offsets are in map pixels with y down
+        let mut map = TileMap::new(10, 10, Vec2::splat(32.));
+        let half_wall = Collider::aabb(Vec2::new(16., 8.)).with_offset(Vec2::new(0., -8.));
+        map.set_partial_collider(IVec2::new(4, 5), half_wall);
+        let (mut world, _) = world_with_map(map, &[IVec2::new(4, 5)]);
+        let mover = spawn_mover(&mut world, IVec2::new(4, 6), Vec2::Y * 100.);
+
+        move_for(&mut world, 60);
+
+        // The mover walks into the bottom half of the tile and stops at its middle
+        let middle = world
+            .resource::<TileMap>()
+            .tile_to_world(IVec2::new(4, 5))
+            .y;
+        let top = world.get::<Transform>(mover).unwrap().translation.y + PLAYER_HALF_EXTENTS.y;
+        assert!((top - middle).abs() < 1e-3);
+    }
+
+    #[test]
+    fn circles_are_pushed_out_along_the_line_between_shapes() {
+        let circle = Collider::circle(10.).at(Vec2::new(-8., 0.));
+        let other = Collider::circle(4.).at(Vec2::new(2., 0.));
+        let (penetration, normal) = circle.contact(&other).unwrap();
+        assert_eq!(penetration, 4.);
+        assert_eq!(normal, Vec2::NEG_X);
+
+        // Against a box the closest point on the box decides the direction
+        let wall = Collider::aabb(Vec2::splat(16.)).at(Vec2::new(20., 20.));
+        let corner = Collider::circle(6.).at(Vec2::new(0., 0.));
+        let (penetration, normal) = corner.contact(&wall).unwrap();
+        assert!((penetration - (6. - 32f32.sqrt())).abs() < 1e-5);
+        assert!(normal.abs_diff_eq(Vec2::new(-1., -1.).normalize(), 1e-5));
+        assert!(!Collider::circle(5.)
+            .at(Vec2::new(-3., 0.))
+            .intersects(&wall));
+
+        // Swapping the shapes flips the normal
+        let (_, normal) = wall.contact(&corner).unwrap();
+        assert!(normal.abs_diff_eq(Vec2::new(1., 1.).normalize(), 1e-5));
+    }
+
+    fn collision_events(world: &mut World) -> (Vec<CollisionPair>, Vec<CollisionPair>) {
+        world.run_system_once(collision_events_system);
+        let started = world
+            .resource_mut::<Events<CollisionStarted>>()
+            .drain()
+            .map(|event| event.0)
+            .collect();
+        let ended = world
+            .resource_mut::<Events<CollisionEnded>>()
+            .drain()
+            .map(|event| event.0)
+            .collect();
+        (started, ended)
+    }
+
+    #[test]
+    fn collisions_start_and_end_once() {
+        let (mut world, _) = world_with_walls(&[]);
+        let sensor = world
+            .spawn((Transform::default(), Collider::circle(8.), Sensor))
+            .id();
+        let mover = spawn_mover(&mut world, IVec2::new(5, 5), Vec2::ZERO);
+        world.get_mut::<Transform>(mover).unwrap().translation = Vec3::new(12., 0., 0.);
+
+        let pair = CollisionPair::new(sensor, mover);
+        assert_eq!(collision_events(&mut world), (vec![pair], vec![]));
+        assert_eq!(collision_events(&mut world), (vec![], vec![]));
+        assert!(world.resource::<Collisions>().contains(mover, sensor));
+
+        world.get_mut::<Transform>(mover).unwrap().translation = Vec3::new(40., 0., 0.);
+        assert_eq!(collision_events(&mut world), (vec![], vec![pair]));
+        assert_eq!(collision_events(&mut world), (vec![], vec![]));
+    }
+
+    #[test]
+    fn walls_that_block_a_mover_collide_with_it() {
+        let (mut world, walls) = world_with_walls(&[IVec2::new(5, 5)]);
+        let mover = spawn_mover(&mut world, IVec2::new(4, 5), Vec2::X * 100.);
+
+        move_for(&mut world, 60);
+
+        let (started, _) = collision_events(&mut world);
+        assert_eq!(started, vec![CollisionPair::new(mover, walls[0])]);
+    }
+
+    #[test]
+    fn projectiles_fly_over_water_that_blocks_enemies() {
+        // A pond tile and a wall further along the same row
+        let mut map = TileMap::new(10, 10, Vec2::splat(32.));
+        let mut tiles = vec![None; 100];
+        tiles[map.tile_index(IVec2::new(5, 5))] = Some(Tile {
+            terrain: Some(Terrain::Water),
+            ..Tile::new(0, 0)
+        });
+        map.layers.push(TileLayer {
+            name: "ground".to_string(),
+            tiles,
+            visible: true,
+            opacity: 1.,
+        });
+        let (water, wall) = (IVec2::new(5, 5), IVec2::new(8, 5));
+        map.set_solid(water, true);
+        map.set_solid(wall, true);
+        let (mut world, walls) = world_with_map(map, &[water, wall]);
+
+        let projectile = spawn_mover(&mut world, IVec2::new(4, 5), Vec2::X * 100.);
+        world.entity_mut(projectile).insert(CollisionLayers::new(
+            [Layer::PlayerProjectile],
+            [Layer::Enemy, Layer::Wall],
+        ));
+        let enemy = spawn_mover(&mut world, IVec2::new(4, 5), Vec2::X * 100.);
+        world.entity_mut(enemy).insert(CollisionLayers::new(
+            [Layer::Enemy],
+            [
+                Layer::Player,
+                Layer::PlayerProjectile,
+                Layer::Wall,
+                Layer::Water,
+            ],
+        ));
+
+        move_for(&mut world, 120);
+
+        let x =
+            |world: &World, entity: Entity| world.get::<Transform>(entity).unwrap().translation.x;
+        assert!(x(&world, enemy) < x(&world, walls[0]) - 16.);
+        assert!(x(&world, projectile) > x(&world, walls[0]) + 16.);
+        assert!(x(&world, projectile) < x(&world, walls[1]) - 16.);
+    }
+
+    #[test]
+    fn projectiles_are_used_up_by_the_first_wall_they_hit() {
+        let column: Vec<IVec2> = (0..10).map(|y| IVec2::new(5, y)).collect();
+        let (mut world, _) = world_with_walls(&column);
+        // Flying at the wall at an angle, a mover would slide up along it
+        let projectile = spawn_mover(&mut world, IVec2::new(4, 8), Vec2::new(100., 100.));
+        world.entity_mut(projectile).insert(Projectile);
+
+        let start = position(&world, projectile);
+        let wall_x = world
+            .resource::<TileMap>()
+            .tile_to_world(IVec2::new(5, 0))
+            .x
+            - 16.;
+        let mut frames = 0;
+        while world.get_entity(projectile).is_some() {
+            // Never pushed flush against the wall and moved along it
+            let now = position(&world, projectile);
+            assert!(now.x + PLAYER_HALF_EXTENTS.x < wall_x);
+            assert!((now.y - start.y - (now.x - start.x)).abs() < 1e-3);
+            move_for(&mut world, 1);
+            frames += 1;
+            assert!(frames < 60);
+        }
+    }
+
+    #[test]
+    fn layers_choose_which_entities_collide() {
+        let (mut world, _) = world_with_walls(&[]);
+        let layers = [
+            CollisionLayers::new([Layer::Pickup], [Layer::Player]),
+            CollisionLayers::new([Layer::Enemy], [Layer::Player, Layer::Pickup]),
+            CollisionLayers::new([Layer::Player], [Layer::Enemy, Layer::Pickup]),
+        ];
+        let [pickup, enemy, player] = layers.map(|layers| {
+            world
+                .spawn((Transform::default(), Collider::circle(8.), layers))
+                .id()
+        });
+
+        // The enemy wants the pickup but the pickup only reacts to the player
+        let (mut started, _) = collision_events(&mut world);
+        let mut expected = vec![
+            CollisionPair::new(pickup, player),
+            CollisionPair::new(enemy, player),
+        ];
+        started.sort_by_key(|pair| pair.entities());
+        expected.sort_by_key(|pair| pair.entities());
+        assert_eq!(started, expected);
+    }
+
+    fn position(world: &World, entity: Entity) -> Vec2 {
+        world
+            .get::<Transform>(entity)
+            .unwrap()
+            .translation
+            .truncate()
+    }
+
+    #[test]
+    fn fast_movers_do_not_tunnel_through_thin_walls() {
+        // A 4 pixel thick wall across the top of its tile
+        let mut map = TileMap::new(10, 10, Vec2::splat(32.));
+        let thin_wall = Collider::aabb(Vec2::new(16., 2.)).with_offset(Vec2::new(0., -14.));
+        map.set_partial_collider(IVec2::new(4, 2), thin_wall);
+        let (mut world, _) = world_with_map(map, &[IVec2::new(4, 2)]);
+
+        // Over 100 pixels a step, more than three tiles
+        let mover = spawn_mover(&mut world, IVec2::new(4, 8), Vec2::new(0., 6400.));
+        move_for(&mut world, 10);
+
+        let wall_bottom = world
+            .resource::<TileMap>()
+            .tile_to_world(IVec2::new(4, 2))
+            .y
+            + 12.;
+        let top = position(&world, mover).y + PLAYER_HALF_EXTENTS.y;
+        assert!((top - wall_bottom).abs() < 1e-3);
+    }
+
+    #[test]
+    fn sliding_along_a_wall_does_not_catch_on_tile_seams() {
+        let column: Vec<IVec2> = (0..10).map(|y| IVec2::new(5, y)).collect();
+        let (mut world, _) = world_with_walls(&column);
+        let mover = spawn_mover(&mut world, IVec2::new(4, 8), Vec2::new(100., 100.));
+        let start = position(&world, mover);
+
+        move_for(&mut world, 60);
+
+        // Pressed against the wall the whole way up, without losing any vertical speed
+        let end = position(&world, mover);
+        let wall_x = world
+            .resource::<TileMap>()
+            .tile_to_world(IVec2::new(5, 0))
+            .x
+            - 16.;
+        assert!((end.x + PLAYER_HALF_EXTENTS.x - wall_x).abs() < 1e-3);
+        assert!((end.y - start.y - 100.).abs() < 1e-2);
+    }
+
+    #[test]
+    fn diagonal_moves_into_an_inside_corner_stop_on_both_axes() {
+        let (mut world, _) = world_with_walls(&[
+            IVec2::new(5, 3),
+            IVec2::new(5, 4),
+            IVec2::new(3, 3),
+            IVec2::new(4, 3),
+        ]);
+        let mover = spawn_mover(&mut world, IVec2::new(4, 4), Vec2::new(300., 300.));
+
+        move_for(&mut world, 30);
+
+        let corner = world.resource::<TileMap>().tile_to_world(IVec2::new(4, 4)) + 16.;
+        let end = position(&world, mover) + PLAYER_HALF_EXTENTS;
+        assert!(end.abs_diff_eq(corner, 1e-3));
+    }
+
+    #[test]
+    fn movers_graze_past_outside_corners() {
+        // Moving up exactly along the side of a single wall tile
+        let (mut world, _) = world_with_walls(&[IVec2::new(5, 4)]);
+        let mover = spawn_mover(&mut world, IVec2::new(4, 6), Vec2::Y * 100.);
+        let wall_x = world
+            .resource::<TileMap>()
+            .tile_to_world(IVec2::new(5, 4))
+            .x
+            - 16.;
+        world.get_mut::<Transform>(mover).unwrap().translation.x = wall_x - PLAYER_HALF_EXTENTS.x;
+        let start = position(&world, mover);
+
+        move_for(&mut world, 60);
+
+        assert!((position(&world, mover).y - start.y - 100.).abs() < 1e-2);
+    }
+
+    #[test]
+    fn movers_inside_a_wall_can_move_out() {
+        let (mut world, _) = world_with_walls(&[IVec2::new(4, 4)]);
+        let mover = spawn_mover(&mut world, IVec2::new(4, 4), Vec2::X * 100.);
+        let start = position(&world, mover);
+
+        move_for(&mut world, 30);
+
+        assert!((position(&world, mover).x - start.x - 50.).abs() < 1e-2);
+    }
+
+    #[test]
+    fn sweeps_find_the_time_and_face_of_impact() {
+        let moving = Aabb2d::new(Vec2::ZERO, Vec2::splat(5.));
+        let wall = Aabb2d::new(Vec2::new(20., 0.), Vec2::splat(5.));
+
+        assert_eq!(
+            sweep_aabb(moving, Vec2::new(20., 0.), wall),
+            Some((0.5, Vec2::NEG_X))
+        );
+        // Too short, moving away, or passing above
+        assert_eq!(sweep_aabb(moving, Vec2::new(9., 0.), wall), None);
+        assert_eq!(sweep_aabb(moving, Vec2::new(-20., 0.), wall), None);
+        assert_eq!(sweep_aabb(moving, Vec2::new(20., 20.), wall), None);
+        // Resting against the wall and pushing into it doesn't move at all
+        let resting = Aabb2d::new(Vec2::new(10., 0.), Vec2::splat(5.));
+        assert_eq!(
+            sweep_aabb(resting, Vec2::new(5., 3.), wall),
+            Some((0., Vec2::NEG_X))
+        );
+    }
+}
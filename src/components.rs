@@ -1,18 +1,49 @@
+use std::collections::HashMap;
+
 use bevy::prelude::*;
 
+use crate::tilemap::PropertyValue;
+
 // Common components
 #[derive(Component, Debug)]
 pub struct Velocity {
-    pub x: f32,
+    pub x: f32, // Pixels per second
     pub y: f32,
 }
 
+// How quickly a mover gets up to speed and how quickly it comes to a stop again.
+// The surface under the mover scales both rates by its traction.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Locomotion {
+    pub max_speed: f32,    // Pixels per second
+    pub acceleration: f32, // Pixels per second squared, while trying to move
+    pub friction: f32,     // Pixels per second squared, while not trying to move
+}
+
+// Direction a mover is trying to go in, at most 1 long. Input and AI set it and the
+// velocity follows.
+#[derive(Component, Debug, Default, Deref, DerefMut)]
+pub struct MoveIntent(pub Vec2);
+
+// Sudden changes of velocity (knockback, explosions) added on the next step
+#[derive(Component, Debug, Default, Deref, DerefMut)]
+pub struct Impulse(pub Vec2);
+
+// Translations of a mover at the end of the last two fixed steps, its Transform is drawn
+// between them
+#[derive(Component, Default)]
+pub struct Interpolated {
+    pub previous: Vec3,
+    pub current: Vec3,
+}
+
 // Movable component to despawn when going out of bounds
 #[derive(Component)]
 pub struct Movable {
     pub auto_despawn: bool,
 }
 
+// Which way a sprite is drawn, diagonals show the up or down sprite
 #[derive(Component, Debug)]
 pub enum Direction {
     Up,
@@ -21,6 +52,53 @@ pub enum Direction {
     Right,
 }
 
+// Unit vector an entity looks in, snapped to one of the 8 compass directions
+#[derive(Component, Debug, Clone, Copy, Deref)]
+pub struct Facing(pub Vec2);
+
+// Solid area of an entity, centered on its transform plus the offset
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct Collider {
+    pub shape: ColliderShape,
+    pub offset: Vec2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColliderShape {
+    Aabb { half_extents: Vec2 },
+    Circle { radius: f32 },
+}
+
+// What a collider is, used to choose what it collides with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Layer {
+    Player,
+    Enemy,
+    PlayerProjectile,
+    EnemyProjectile,
+    Wall,
+    Water,
+    Pickup,
+}
+
+// Bitmasks of the layers a collider is on and the layers it collides with. Two colliders
+// only collide when each one's filters include a layer of the other. Colliders without
+// this component are on every layer and collide with every layer.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CollisionLayers {
+    pub memberships: u32,
+    pub filters: u32,
+}
+
+// A collider that only reports overlaps through collision events, it never blocks movement
+// and walls never block it
+#[derive(Component)]
+pub struct Sensor;
+
+// Despawned as soon as it hits anything but a sensor
+#[derive(Component)]
+pub struct Projectile;
+
 // Animation components
 #[derive(Component, Debug)]
 pub struct AnimationIndices {
@@ -35,7 +113,55 @@ pub struct AnimationTimer(pub Timer);
 #[derive(Component)]
 pub struct Player; // Just used as a marker
 
+// Enemy components
+#[derive(Component)]
+pub struct Enemy;
+
+#[derive(Component)]
+pub struct Mole {
+    pub vertical: bool, // Moles patrol either vertically or horizontally
+}
+
+#[derive(Component)]
+pub struct Treant;
+
 // Tilemap components
 #[derive(Component)]
-pub struct TileCollider;
+pub struct MapRoot; // Parent of every tile, collider and object of the current map
+
+#[derive(Component)]
+pub struct TileChunk(pub IVec2); // Chunk coordinate, parent of the chunk's tiles and colliders
+
+// A tile sprite showing the frames of an animated tile
+#[derive(Component)]
+pub struct AnimatedTile {
+    pub tileset: usize,
+    pub index: usize, // Animated tile as placed in the map, not the frame shown
+}
+
+// A chunk mesh containing animated tiles, rebuilt when their frames change
+#[derive(Component)]
+pub struct AnimatedTileMesh {
+    pub chunk: IVec2,
+    pub layer: usize,
+    pub tileset: usize,
+}
+
+#[derive(Component)]
+pub struct TileCollider; // Marks the colliders of map tiles, which never move
+
+#[derive(Component)]
+pub struct LevelExit {
+    pub destination: Option<String>, // Map to load, relative to the assets folder
+    pub spawn_point: Option<String>, // Named spawn point in the destination map
+}
+
+// Item the player collects by walking over it
+#[derive(Component)]
+pub struct Pickup {
+    pub item: String,
+}
 
+// Custom properties of a map object, copied from Tiled as-is
+#[derive(Component, Debug, Deref)]
+pub struct ObjectProperties(pub HashMap<String, PropertyValue>);
@@ -1,3 +1,5 @@
+use bevy::math::Vec2;
+
 // asset constants
 // For assets, bevy already assumes in an 'assets' directory
 pub const PLAYER_SPRITE_FRONT: &str =
@@ -7,16 +9,43 @@ pub const PLAYER_SPRITE_BACK: &str =
 pub const PLAYER_SPRITE_SIDE: &str =
     "tiny-RPG-forest-files/PNG/sprites/hero/idle/hero-idle-side/hero-idle-side.png";
 pub const PLAYER_SIZE: (f32, f32) = (144., 75.);
+pub const PLAYER_HALF_EXTENTS: Vec2 = Vec2::new(6., 11.); // Hitbox around the player's feet and body
+pub const PLAYER_SPEED: f32 = 100.; // Pixels per second
+pub const PLAYER_RUN_SPEED: f32 = 160.; // Pixels per second, while holding run
+pub const PLAYER_ACCELERATION: f32 = 900.;
+pub const PLAYER_FRICTION: f32 = 1200.;
+pub const PLAYER_KNOCKBACK: f32 = 250.; // Speed the player is pushed away from enemies with
+
+pub const MOLE_SPRITE: &str = "tiny-RPG-forest-files/PNG/sprites/mole/idle/mole-idle-front.png";
+pub const TREANT_SPRITE: &str =
+    "tiny-RPG-forest-files/PNG/sprites/treant/idle/treant-idle-front.png";
+
+pub const COIN_SPRITE: &str = "tiny-RPG-forest-files/PNG/sprites/misc/coin/coin-1.png";
+pub const GEM_SPRITE: &str = "tiny-RPG-forest-files/PNG/sprites/misc/gem/gem-1.png";
 
 pub const LASER_SPRITE: &str = "laser_a_01.png";
 pub const LASER_SIZE: (f32, f32) = (9., 54.);
 pub const LASER_SCALE: f32 = 0.2;
+pub const LASER_SPEED: f32 = 200.;
 
 // Game constants
-pub const TIME_STEP: f32 = 1. / 60.; // 60 fps
-pub const BASE_SPEED: f32 = 100.;
+pub const FIXED_STEP_HZ: f64 = 60.; // Gameplay steps per second, whatever the frame rate
 pub const MARGIN: f32 = 200.;
 pub const RESOLUTION: f32 = 16.0 / 9.0;
 
 // Tilemap constants
 pub const TILE_SIZE: f32 = 32.;
+pub const ASCII_SHEET: &str = "tilemap/Ascii.png";
+pub const ASCII_MAP: &str = "tilemap/Ascii.txt";
+pub const TILED_MAP: &str = "tiny-RPG-forest-files/Demo/assets/maps/map.json";
+pub const START_MAP: &str = TILED_MAP; // Either format works, the loader is picked by extension
+
+// Keys for each input action, the defaults are used for actions the file leaves out
+pub const INPUT_BINDINGS: &str = "input.bindings";
+pub const GAMEPAD_DEADZONE: f32 = 0.2; // Share of the left stick's range that is ignored
+pub const TILE_LAYER_Z: f32 = -10.; // Tile layers are stacked upwards from here, below sprites
+pub const TILE_ANIMATION_STEP: f32 = 0.05; // Seconds between animated tile frame checks
+
+pub const CHUNK_SIZE: u32 = 16; // Chunks are CHUNK_SIZE x CHUNK_SIZE tiles
+pub const CHUNK_LOAD_DISTANCE: f32 = 1024.; // Chunks closer than this to the camera are spawned
+pub const CHUNK_UNLOAD_DISTANCE: f32 = 1536.; // And despawned again once further than this
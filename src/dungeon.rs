@@ -0,0 +1,554 @@
+use std::collections::{HashMap, VecDeque};
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+
+use crate::ascii::{ascii_tileset, ascii_tileset_description};
+use crate::autotile::Terrain;
+use crate::components::Collider;
+use crate::tilemap::{
+    MapObject, PropertyValue, Tile, TileLayer, TileMap, TileMapLoaderError, Tileset,
+};
+use crate::TILE_SIZE;
+
+const ROOM_MIN_SIZE: i32 = 4;
+const ROOM_MAX_SIZE: i32 = 10;
+const ENEMY_MIN_DISTANCE: u32 = 6; // Enemies are never placed closer than this to the start, in steps
+
+// Small deterministic generator (SplitMix64), so a seed gives the same dungeon everywhere
+pub struct SeededRng(u64);
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        SeededRng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniform integer in min..max, max must be greater than min
+    pub fn range(&mut self, min: i32, max: i32) -> i32 {
+        min + (self.next_u64() % (max - min) as u64) as i32
+    }
+
+    // True with the given probability
+    pub fn chance(&mut self, probability: f32) -> bool {
+        ((self.next_u64() >> 40) as f32 / (1 << 24) as f32) < probability
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DungeonKind {
+    Rooms, // Rectangular rooms joined by corridors
+    Caves, // Cellular automata caves
+}
+
+// What to generate, read from .dungeon files (JSON)
+#[derive(Deserialize, Debug, Clone)]
+pub struct DungeonSettings {
+    pub kind: DungeonKind,
+    pub seed: u64,
+    pub width: u32,
+    pub height: u32,
+    #[serde(default)]
+    pub enemies: u32, // Moles placed on random floor tiles
+    #[serde(default)]
+    pub exit: Option<String>, // Map the exit leads to, there is no exit if None
+    #[serde(default = "default_max_rooms")]
+    pub max_rooms: u32, // Rooms only
+    #[serde(default = "default_fill")]
+    pub fill: f32, // Caves only, chance of a cell starting as wall
+    #[serde(default = "default_smoothing")]
+    pub smoothing: u32, // Caves only, cellular automata steps
+}
+
+fn default_max_rooms() -> u32 {
+    12
+}
+
+fn default_fill() -> f32 {
+    0.45
+}
+
+fn default_smoothing() -> u32 {
+    5
+}
+
+// Walls of a generated level and where the player starts and leaves
+struct DungeonLayout {
+    width: i32,
+    height: i32,
+    walls: Vec<bool>, // Row-major with row 0 at the top, like TileMap
+    start: IVec2,
+    exit: IVec2,
+}
+
+impl DungeonLayout {
+    fn filled(width: u32, height: u32) -> Self {
+        DungeonLayout {
+            width: width as i32,
+            height: height as i32,
+            walls: vec![true; (width * height) as usize],
+            start: IVec2::ZERO,
+            exit: IVec2::ZERO,
+        }
+    }
+
+    fn contains(&self, cell: IVec2) -> bool {
+        cell.x >= 0 && cell.y >= 0 && cell.x < self.width && cell.y < self.height
+    }
+
+    fn index(&self, cell: IVec2) -> usize {
+        (cell.y * self.width + cell.x) as usize
+    }
+
+    fn cell(&self, index: usize) -> IVec2 {
+        IVec2::new(index as i32 % self.width, index as i32 / self.width)
+    }
+
+    // Cells outside the layout count as walls
+    fn is_wall(&self, cell: IVec2) -> bool {
+        !self.contains(cell) || self.walls[self.index(cell)]
+    }
+
+    fn carve(&mut self, cell: IVec2) {
+        let index = self.index(cell);
+        self.walls[index] = false;
+    }
+
+    fn is_border(&self, cell: IVec2) -> bool {
+        cell.x == 0 || cell.y == 0 || cell.x == self.width - 1 || cell.y == self.height - 1
+    }
+
+    // Steps needed to reach every floor cell from a cell, None for unreachable cells
+    fn distances(&self, from: IVec2) -> Vec<Option<u32>> {
+        let mut distances = vec![None; self.walls.len()];
+        let mut queue = VecDeque::from([from]);
+        distances[self.index(from)] = Some(0);
+        while let Some(cell) = queue.pop_front() {
+            let distance = distances[self.index(cell)].unwrap_or(0);
+            for offset in [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y] {
+                let next = cell + offset;
+                if self.is_wall(next) || distances[self.index(next)].is_some() {
+                    continue;
+                }
+                distances[self.index(next)] = Some(distance + 1);
+                queue.push_back(next);
+            }
+        }
+        distances
+    }
+
+    // The floor cell that takes the most steps to reach from a cell
+    fn farthest_from(&self, from: IVec2) -> IVec2 {
+        let distances = self.distances(from);
+        self.cell(
+            (0..distances.len())
+                .max_by_key(|index| distances[*index])
+                .unwrap_or(0),
+        )
+    }
+}
+
+// Generate a level from its settings, the same settings always give the same map.
+// Walls are drawn with the given tileset's '#' glyph and autotiled, floors with '.'.
+pub fn generate_dungeon(
+    settings: &DungeonSettings,
+    tileset: Tileset,
+) -> Result<TileMap, TileMapLoaderError> {
+    if settings.width < ROOM_MAX_SIZE as u32 + 2 || settings.height < ROOM_MAX_SIZE as u32 + 2 {
+        return Err(TileMapLoaderError::Invalid(format!(
+            "dungeons must be at least {0}x{0} tiles",
+            ROOM_MAX_SIZE + 2
+        )));
+    }
+
+    let mut rng = SeededRng::new(settings.seed);
+    let layout = match settings.kind {
+        DungeonKind::Rooms => generate_rooms(settings, &mut rng),
+        DungeonKind::Caves => generate_caves(settings, &mut rng)?,
+    };
+    let mut map = TileMap::new(settings.width, settings.height, Vec2::splat(TILE_SIZE));
+    map.tilesets.push(tileset);
+    map.solid.clone_from(&layout.walls);
+
+    // Only walls next to a floor are drawn, the rest of the rock stays empty
+    let tiles = (0..layout.walls.len())
+        .map(|index| {
+            let cell = layout.cell(index);
+            let exposed = (-1..=1)
+                .flat_map(|y| (-1..=1).map(move |x| cell + IVec2::new(x, y)))
+                .any(|neighbour| !layout.is_wall(neighbour));
+            let (glyph, color, terrain) = match layout.walls[index] {
+                true if !exposed => return None,
+                true => ('#', Color::rgb(0.9, 0.9, 0.9), Some(Terrain::Wall)),
+                false => ('.', Color::rgb(0.3, 0.3, 0.3), None),
+            };
+            Some(Tile {
+                color,
+                terrain,
+                collider: terrain.map(|_| Collider::aabb(Vec2::splat(TILE_SIZE / 2.))),
+                ..Tile::new(0, glyph as usize)
+            })
+        })
+        .collect();
+    map.layers.push(TileLayer {
+        name: "dungeon".to_string(),
+        tiles,
+        visible: true,
+        opacity: 1.,
+    });
+
+    let object = |kind: &str, name: &str, cell: IVec2| MapObject {
+        name: name.to_string(),
+        kind: kind.to_string(),
+        position: (cell.as_vec2() + 0.5) * map.grid.tile_size,
+        size: map.grid.tile_size,
+        properties: HashMap::new(),
+    };
+    let mut objects = vec![object("player", "start", layout.start)];
+    if let Some(destination) = &settings.exit {
+        let mut exit = object("exit", "", layout.exit);
+        exit.properties = HashMap::from([
+            (
+                "map".to_string(),
+                PropertyValue::String(destination.clone()),
+            ),
+            (
+                "spawn".to_string(),
+                PropertyValue::String("start".to_string()),
+            ),
+        ]);
+        objects.push(exit);
+    }
+
+    // Enemies go on random floor cells that can be reached but are not right next to the start
+    let distances = layout.distances(layout.start);
+    let candidates: Vec<IVec2> = (0..layout.walls.len())
+        .filter(|index| distances[*index].is_some_and(|distance| distance >= ENEMY_MIN_DISTANCE))
+        .map(|index| layout.cell(index))
+        .filter(|cell| *cell != layout.exit)
+        .collect();
+    if !candidates.is_empty() {
+        for _ in 0..settings.enemies {
+            let cell = candidates[rng.range(0, candidates.len() as i32) as usize];
+            objects.push(object("mole", "", cell));
+        }
+    }
+    map.objects = objects;
+
+    map.autotile();
+    Ok(map)
+}
+
+// Place non-overlapping rooms and join each one to the previous with an L-shaped corridor.
+// The player starts in the first room and the exit is in the last one, or in the far corner
+// of the first room when no other room fit.
+fn generate_rooms(settings: &DungeonSettings, rng: &mut SeededRng) -> DungeonLayout {
+    let mut layout = DungeonLayout::filled(settings.width, settings.height);
+    let mut rooms: Vec<IRect> = Vec::new();
+
+    let max_rooms = settings.max_rooms.max(2);
+    for _ in 0..max_rooms * 8 {
+        if rooms.len() as u32 >= max_rooms {
+            break;
+        }
+        let size = IVec2::new(
+            rng.range(ROOM_MIN_SIZE, ROOM_MAX_SIZE + 1),
+            rng.range(ROOM_MIN_SIZE, ROOM_MAX_SIZE + 1),
+        );
+        let min = IVec2::new(
+            rng.range(1, layout.width - size.x),
+            rng.range(1, layout.height - size.y),
+        );
+        let room = IRect::from_corners(min, min + size);
+
+        // Keep at least one wall between rooms
+        let margin = IRect::from_corners(room.min - 1, room.max + 1);
+        if rooms
+            .iter()
+            .any(|other| !margin.intersect(*other).is_empty())
+        {
+            continue;
+        }
+
+        for y in room.min.y..room.max.y {
+            for x in room.min.x..room.max.x {
+                layout.carve(IVec2::new(x, y));
+            }
+        }
+        if let Some(previous) = rooms.last() {
+            carve_corridor(&mut layout, rng, previous.center(), room.center());
+        }
+        rooms.push(room);
+    }
+
+    // The first room always fits since the map is larger than the largest room
+    layout.start = rooms[0].center();
+    layout.exit = match rooms.len() {
+        1 => layout.farthest_from(layout.start),
+        _ => rooms[rooms.len() - 1].center(),
+    };
+    layout
+}
+
+fn carve_corridor(layout: &mut DungeonLayout, rng: &mut SeededRng, from: IVec2, to: IVec2) {
+    let corner = if rng.chance(0.5) {
+        IVec2::new(to.x, from.y)
+    } else {
+        IVec2::new(from.x, to.y)
+    };
+    for (start, end) in [(from, corner), (corner, to)] {
+        let min = start.min(end);
+        let max = start.max(end);
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                layout.carve(IVec2::new(x, y));
+            }
+        }
+    }
+}
+
+// Fill the map with random walls and smooth them into caves, then keep only the largest
+// cave so every floor cell can be reached. The exit is the floor cell furthest from the start.
+fn generate_caves(
+    settings: &DungeonSettings,
+    rng: &mut SeededRng,
+) -> Result<DungeonLayout, TileMapLoaderError> {
+    let mut layout = DungeonLayout::filled(settings.width, settings.height);
+    for index in 0..layout.walls.len() {
+        let cell = layout.cell(index);
+        layout.walls[index] = layout.is_border(cell) || rng.chance(settings.fill);
+    }
+
+    for _ in 0..settings.smoothing {
+        layout.walls = (0..layout.walls.len())
+            .map(|index| {
+                let cell = layout.cell(index);
+                let walls_around = (-1..=1)
+                    .flat_map(|y| (-1..=1).map(move |x| IVec2::new(x, y)))
+                    .filter(|offset| *offset != IVec2::ZERO && layout.is_wall(cell + *offset))
+                    .count();
+                match walls_around {
+                    _ if layout.is_border(cell) => true,
+                    5.. => true,
+                    4 => layout.walls[index],
+                    _ => false,
+                }
+            })
+            .collect();
+    }
+
+    // Flood fill every cave and fill in all but the largest one
+    let mut largest: Vec<usize> = Vec::new();
+    let mut visited = vec![false; layout.walls.len()];
+    for index in 0..layout.walls.len() {
+        if layout.walls[index] || visited[index] {
+            continue;
+        }
+        let distances = layout.distances(layout.cell(index));
+        let cave: Vec<usize> = (0..distances.len())
+            .filter(|i| distances[*i].is_some())
+            .collect();
+        for i in cave.iter() {
+            visited[*i] = true;
+        }
+        if cave.len() > largest.len() {
+            largest = cave;
+        }
+    }
+    // The exit needs a cell of its own
+    if largest.len() < 2 {
+        return Err(TileMapLoaderError::Invalid(format!(
+            "seed {} produced a cave too small for a start and an exit",
+            settings.seed
+        )));
+    }
+    layout.walls = vec![true; layout.walls.len()];
+    for index in largest.iter() {
+        layout.walls[*index] = false;
+    }
+
+    layout.start = layout.cell(largest[rng.range(0, largest.len() as i32) as usize]);
+    layout.exit = layout.farthest_from(layout.start);
+    Ok(layout)
+}
+
+// Loads .dungeon files, JSON settings that are turned into a map when the file is loaded
+#[derive(Default)]
+pub struct DungeonLoader;
+
+impl AssetLoader for DungeonLoader {
+    type Asset = TileMap;
+    type Settings = ();
+    type Error = TileMapLoaderError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<TileMap, TileMapLoaderError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            let settings: DungeonSettings = serde_json::from_slice(&bytes)?;
+            generate_dungeon(
+                &settings,
+                ascii_tileset(&ascii_tileset_description(), load_context),
+            )
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["dungeon"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(kind: DungeonKind, seed: u64) -> DungeonSettings {
+        DungeonSettings {
+            kind,
+            seed,
+            width: 60,
+            height: 40,
+            enemies: 4,
+            exit: Some("next.dungeon".to_string()),
+            max_rooms: default_max_rooms(),
+            fill: default_fill(),
+            smoothing: default_smoothing(),
+        }
+    }
+
+    fn layout(settings: &DungeonSettings) -> DungeonLayout {
+        let mut rng = SeededRng::new(settings.seed);
+        match settings.kind {
+            DungeonKind::Rooms => generate_rooms(settings, &mut rng),
+            DungeonKind::Caves => generate_caves(settings, &mut rng).unwrap(),
+        }
+    }
+
+    fn tileset() -> Tileset {
+        Tileset {
+            name: "ascii".to_string(),
+            texture: Handle::default(),
+            layout: Handle::default(),
+            animations: HashMap::new(),
+            autotiles: HashMap::new(),
+            traction: HashMap::new(),
+        }
+    }
+
+    fn generate(settings: &DungeonSettings) -> TileMap {
+        generate_dungeon(settings, tileset()).unwrap()
+    }
+
+    fn objects(map: &TileMap) -> Vec<(String, String, Vec2)> {
+        map.objects
+            .iter()
+            .map(|object| (object.kind.clone(), object.name.clone(), object.position))
+            .collect()
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_dungeon() {
+        for kind in [DungeonKind::Rooms, DungeonKind::Caves] {
+            let first = generate(&settings(kind, 42));
+            let second = generate(&settings(kind, 42));
+            assert_eq!(first.solid, second.solid, "{:?}", kind);
+            assert_eq!(objects(&first), objects(&second), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn different_seeds_give_different_dungeons() {
+        for kind in [DungeonKind::Rooms, DungeonKind::Caves] {
+            let first = generate(&settings(kind, 1));
+            let second = generate(&settings(kind, 2));
+            assert_ne!(first.solid, second.solid, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn every_floor_cell_can_be_reached_from_the_start() {
+        for kind in [DungeonKind::Rooms, DungeonKind::Caves] {
+            for seed in 0..20 {
+                let layout = layout(&settings(kind, seed));
+                let distances = layout.distances(layout.start);
+                for (index, distance) in distances.iter().enumerate() {
+                    assert!(
+                        layout.walls[index] || distance.is_some(),
+                        "{:?} seed {}: {} cannot be reached",
+                        kind,
+                        seed,
+                        layout.cell(index)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn the_exit_is_never_at_the_start() {
+        for kind in [DungeonKind::Rooms, DungeonKind::Caves] {
+            for seed in 0..20 {
+                let map = generate(&settings(kind, seed));
+                let start = map.objects.iter().find(|object| object.kind == "player");
+                let exit = map.objects.iter().find(|object| object.kind == "exit");
+                assert_ne!(
+                    start.unwrap().position,
+                    exit.unwrap().position,
+                    "{:?} seed {}",
+                    kind,
+                    seed
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn single_rooms_put_the_exit_in_their_far_corner() {
+        // The smallest map often only fits one room
+        let mut settings = settings(DungeonKind::Rooms, 0);
+        settings.width = ROOM_MAX_SIZE as u32 + 2;
+        settings.height = ROOM_MAX_SIZE as u32 + 2;
+        let mut single_rooms = 0;
+        for seed in 0..20 {
+            settings.seed = seed;
+            let layout = layout(&settings);
+            let distances = layout.distances(layout.start);
+            let floor: Vec<IVec2> = (0..distances.len())
+                .filter(|index| distances[*index].is_some())
+                .map(|index| layout.cell(index))
+                .collect();
+            let min = floor.iter().copied().reduce(IVec2::min).unwrap();
+            let size = floor.iter().copied().reduce(IVec2::max).unwrap() - min + 1;
+            if floor.len() as i32 != size.x * size.y {
+                continue; // The floor isn't a single rectangle, so another room fit
+            }
+            single_rooms += 1;
+            assert_ne!(layout.exit, layout.start, "seed {}", seed);
+            assert_eq!(
+                distances[layout.index(layout.exit)],
+                distances.iter().copied().max().flatten(),
+                "seed {}",
+                seed
+            );
+            assert!(generate_dungeon(&settings, tileset()).is_ok());
+        }
+        assert!(single_rooms > 0);
+    }
+}
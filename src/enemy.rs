@@ -0,0 +1,61 @@
+use crate::components::{Collider, CollisionLayers, Enemy, Layer, Mole, Treant};
+use crate::constants::{MOLE_SPRITE, TREANT_SPRITE};
+use crate::objects::MapObjectAppExt;
+use crate::tilemap::MapObject;
+use bevy::{
+    ecs::system::EntityCommands,
+    prelude::*,
+    render::texture::{ImageLoaderSettings, ImageSampler},
+};
+
+pub struct EnemyPlugin;
+
+impl Plugin for EnemyPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_map_object("mole", spawn_mole)
+            .register_map_object("treant", spawn_treant);
+    }
+}
+
+fn spawn_mole(entity: &mut EntityCommands, object: &MapObject, asset_server: &AssetServer) {
+    entity.insert((
+        load_enemy_sprite(asset_server, MOLE_SPRITE),
+        Enemy,
+        Collider::circle(9.),
+        enemy_layers(),
+        Mole {
+            vertical: object.property_bool("vertical").unwrap_or(false),
+        },
+    ));
+}
+
+fn spawn_treant(entity: &mut EntityCommands, _object: &MapObject, asset_server: &AssetServer) {
+    entity.insert((
+        load_enemy_sprite(asset_server, TREANT_SPRITE),
+        Enemy,
+        Collider::aabb(Vec2::new(8., 6.)).with_offset(Vec2::new(0., -11.)), // Only the trunk
+        enemy_layers(),
+        Treant,
+    ));
+}
+
+// Enemies walk around water and are only hurt by the player's projectiles
+fn enemy_layers() -> CollisionLayers {
+    CollisionLayers::new(
+        [Layer::Enemy],
+        [
+            Layer::Player,
+            Layer::PlayerProjectile,
+            Layer::Wall,
+            Layer::Water,
+        ],
+    )
+}
+
+// The entity already has its transform and visibility, so only the sprite parts are added
+fn load_enemy_sprite(asset_server: &AssetServer, path: &'static str) -> (Sprite, Handle<Image>) {
+    let texture = asset_server.load_with_settings(path, |settings: &mut ImageLoaderSettings| {
+        settings.sampler = ImageSampler::nearest()
+    });
+    (Sprite::default(), texture)
+}
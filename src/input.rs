@@ -0,0 +1,601 @@
+use std::collections::HashMap;
+
+use crate::constants::{GAMEPAD_DEADZONE, INPUT_BINDINGS};
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    input::InputSystem,
+    prelude::*,
+    utils::BoxedFuture,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+// Gameplay reads what the player wants to do from ButtonInput<Action> and MoveAxis instead
+// of from raw keys and gamepads, so the keys and buttons behind each action can come from a
+// config file and be changed while the game runs.
+pub struct ActionsPlugin;
+
+impl Plugin for ActionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<ActionBindings>()
+            .register_asset_loader(BindingsLoader)
+            .insert_resource(ActionBindings::defaults())
+            .init_resource::<ButtonInput<Action>>()
+            .init_resource::<MoveAxis>()
+            .init_resource::<Rebinding>()
+            .add_systems(Startup, load_bindings)
+            .add_systems(
+                PreUpdate,
+                (apply_bindings_system, rebind_system, actions_system)
+                    .chain()
+                    .in_set(ActionSystem)
+                    .after(InputSystem),
+            );
+    }
+}
+
+// Systems that fill ButtonInput<Action> for the frame
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ActionSystem;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Attack,
+    Interact,
+    Pause,
+    Run,
+}
+
+impl Action {
+    pub const ALL: [Action; 8] = [
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Attack,
+        Action::Interact,
+        Action::Pause,
+        Action::Run,
+    ];
+}
+
+// Where the player is trying to move, at most 1 long. The left stick of a gamepad gives
+// anything in between, the move actions give the 8 directions at full length.
+#[derive(Resource, Debug, Default, Deref)]
+pub struct MoveAxis(pub Vec2);
+
+// Keys and gamepad buttons bound to each action, any of them triggers it. Also the asset a
+// bindings file loads into, e.g.
+// { "keys": { "Attack": ["Space", "KeyJ"] }, "buttons": { "Attack": ["South", "West"] } }
+#[derive(Asset, TypePath, Resource, Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ActionBindings {
+    pub keys: HashMap<Action, Vec<KeyCode>>,
+    pub buttons: HashMap<Action, Vec<GamepadButtonType>>,
+}
+
+impl ActionBindings {
+    // WASD and the arrows both move, so do the gamepad's d-pad and left stick
+    pub fn defaults() -> Self {
+        use GamepadButtonType::*;
+        ActionBindings {
+            keys: HashMap::from([
+                (Action::MoveUp, vec![KeyCode::KeyW, KeyCode::ArrowUp]),
+                (Action::MoveDown, vec![KeyCode::KeyS, KeyCode::ArrowDown]),
+                (Action::MoveLeft, vec![KeyCode::KeyA, KeyCode::ArrowLeft]),
+                (Action::MoveRight, vec![KeyCode::KeyD, KeyCode::ArrowRight]),
+                (Action::Attack, vec![KeyCode::Space]),
+                (Action::Interact, vec![KeyCode::KeyE]),
+                (Action::Pause, vec![KeyCode::Escape, KeyCode::KeyP]),
+                (Action::Run, vec![KeyCode::ShiftLeft, KeyCode::ShiftRight]),
+            ]),
+            buttons: HashMap::from([
+                (Action::MoveUp, vec![DPadUp]),
+                (Action::MoveDown, vec![DPadDown]),
+                (Action::MoveLeft, vec![DPadLeft]),
+                (Action::MoveRight, vec![DPadRight]),
+                (Action::Attack, vec![South, West]),
+                (Action::Interact, vec![North]),
+                (Action::Pause, vec![Start]),
+                (Action::Run, vec![East, RightTrigger2]),
+            ]),
+        }
+    }
+
+    pub fn keys(&self, action: Action) -> &[KeyCode] {
+        self.keys.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn buttons(&self, action: Action) -> &[GamepadButtonType] {
+        self.buttons.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    // Add a key to an action. A key only does one thing, so it is taken off any other action.
+    pub fn bind(&mut self, action: Action, key: KeyCode) {
+        bind(&mut self.keys, action, key);
+    }
+
+    // Make a single key the only one for an action
+    pub fn rebind(&mut self, action: Action, key: KeyCode) {
+        self.keys.remove(&action);
+        self.bind(action, key);
+    }
+
+    pub fn bind_button(&mut self, action: Action, button: GamepadButtonType) {
+        bind(&mut self.buttons, action, button);
+    }
+
+    pub fn rebind_button(&mut self, action: Action, button: GamepadButtonType) {
+        self.buttons.remove(&action);
+        self.bind_button(action, button);
+    }
+
+    // Replace the keys and buttons of the actions another set of bindings names, e.g. a
+    // bindings file. They are bound one by one so they are taken off the actions they had.
+    pub fn apply(&mut self, other: &ActionBindings) {
+        for action in Action::ALL {
+            if let Some(keys) = other.keys.get(&action) {
+                self.keys.remove(&action);
+                for key in keys {
+                    self.bind(action, *key);
+                }
+            }
+            if let Some(buttons) = other.buttons.get(&action) {
+                self.buttons.remove(&action);
+                for button in buttons {
+                    self.bind_button(action, *button);
+                }
+            }
+        }
+    }
+}
+
+fn bind<T: PartialEq>(bindings: &mut HashMap<Action, Vec<T>>, action: Action, input: T) {
+    for inputs in bindings.values_mut() {
+        inputs.retain(|bound| *bound != input);
+    }
+    bindings.entry(action).or_default().push(input);
+}
+
+// Set to an action to bind the next key or gamepad button pressed to it. Pressing
+// REBIND_KEY walks through every action this way, pressing it again stops. Bindings made
+// this way only last for the session, editing the bindings file replaces them.
+#[derive(Resource, Default)]
+pub struct Rebinding(pub Option<Action>);
+
+const REBIND_KEY: KeyCode = KeyCode::F2;
+
+#[derive(Debug, Error)]
+pub enum BindingsLoaderError {
+    #[error("could not read bindings file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse bindings JSON: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+// Loads .bindings files, JSON objects from action names to lists of key and button names
+#[derive(Default)]
+pub struct BindingsLoader;
+
+impl AssetLoader for BindingsLoader {
+    type Asset = ActionBindings;
+    type Settings = ();
+    type Error = BindingsLoaderError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<ActionBindings, BindingsLoaderError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Ok(serde_json::from_slice(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["bindings"]
+    }
+}
+
+// Kept so the bindings file stays loaded and is watched for changes
+#[derive(Resource)]
+struct BindingsFile(Handle<ActionBindings>);
+
+fn load_bindings(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(BindingsFile(asset_server.load(INPUT_BINDINGS)));
+}
+
+// Use the bindings file once it loads and whenever it is edited. Actions it leaves out
+// keep their default keys and buttons, unless the file gives them to another action.
+// Without a file the defaults stay.
+fn apply_bindings_system(
+    mut events: EventReader<AssetEvent<ActionBindings>>,
+    file: Res<BindingsFile>,
+    files: Res<Assets<ActionBindings>>,
+    mut bindings: ResMut<ActionBindings>,
+) {
+    for event in events.read() {
+        let (AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }) = event
+        else {
+            continue;
+        };
+        if *id != file.0.id() {
+            continue;
+        }
+        if let Some(loaded) = files.get(*id) {
+            let mut merged = ActionBindings::defaults();
+            merged.apply(loaded);
+            *bindings = merged;
+        }
+    }
+}
+
+// An action is held while any of its keys or buttons is, on any connected gamepad. Gamepads
+// can be plugged in and out at any time, Gamepads only lists the ones connected right now.
+fn actions_system(
+    keys: Res<ButtonInput<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    buttons: Res<ButtonInput<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    bindings: Res<ActionBindings>,
+    mut actions: ResMut<ButtonInput<Action>>,
+    mut move_axis: ResMut<MoveAxis>,
+) {
+    let stick = gamepads
+        .iter()
+        .map(|gamepad| left_stick(gamepad, &axes))
+        .max_by(|a, b| a.length_squared().total_cmp(&b.length_squared()))
+        .unwrap_or(Vec2::ZERO);
+
+    // Clearing only forgets last frame's presses and releases, held actions stay held
+    actions.clear();
+    for action in Action::ALL {
+        let pressed = keys.any_pressed(bindings.keys(action).iter().copied())
+            || gamepads.iter().any(|gamepad| {
+                buttons.any_pressed(
+                    bindings
+                        .buttons(action)
+                        .iter()
+                        .map(|button| GamepadButton::new(gamepad, *button)),
+                )
+            })
+            // Pushing the stick most of the way counts as the move action, e.g. for menus
+            || match action {
+                Action::MoveUp => stick.y > 0.5,
+                Action::MoveDown => stick.y < -0.5,
+                Action::MoveLeft => stick.x < -0.5,
+                Action::MoveRight => stick.x > 0.5,
+                _ => false,
+            };
+        if pressed {
+            actions.press(action);
+        } else {
+            actions.release(action);
+        }
+    }
+
+    // The stick is used as is, the move actions only when it is left alone. Left wins over
+    // right and down over up when both are held.
+    move_axis.0 = if stick != Vec2::ZERO {
+        stick
+    } else {
+        let x = if actions.pressed(Action::MoveLeft) {
+            -1.
+        } else if actions.pressed(Action::MoveRight) {
+            1.
+        } else {
+            0.
+        };
+        let y = if actions.pressed(Action::MoveDown) {
+            -1.
+        } else if actions.pressed(Action::MoveUp) {
+            1.
+        } else {
+            0.
+        };
+        Vec2::new(x, y).normalize_or_zero()
+    };
+}
+
+// Position of a gamepad's left stick, zero inside the deadzone so a stick that doesn't quite
+// center doesn't creep. Outside it the rest of the range is stretched back to 0..1.
+fn left_stick(gamepad: Gamepad, axes: &Axis<GamepadAxis>) -> Vec2 {
+    let axis = |axis_type| axes.get(GamepadAxis::new(gamepad, axis_type)).unwrap_or(0.);
+    let stick = Vec2::new(
+        axis(GamepadAxisType::LeftStickX),
+        axis(GamepadAxisType::LeftStickY),
+    );
+    let length = stick.length();
+    if length <= GAMEPAD_DEADZONE {
+        return Vec2::ZERO;
+    }
+    let scaled = ((length - GAMEPAD_DEADZONE) / (1. - GAMEPAD_DEADZONE)).min(1.);
+    stick / length * scaled
+}
+
+// Bind the key or button pressed while an action is waiting for one. The press is used up,
+// so it doesn't also trigger whatever it was bound to, or its new action, while held.
+fn rebind_system(
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    mut buttons: ResMut<ButtonInput<GamepadButton>>,
+    mut rebinding: ResMut<Rebinding>,
+    mut bindings: ResMut<ActionBindings>,
+) {
+    if keys.just_pressed(REBIND_KEY) {
+        keys.reset(REBIND_KEY);
+        rebinding.0 = match rebinding.0 {
+            Some(_) => {
+                info!("Stopped rebinding");
+                None
+            }
+            None => Some(Action::ALL[0]),
+        };
+        prompt(rebinding.0);
+        return;
+    }
+    let Some(action) = rebinding.0 else {
+        return;
+    };
+
+    let key = keys.get_just_pressed().next().copied();
+    let button = buttons.get_just_pressed().next().copied();
+    if let Some(key) = key {
+        keys.reset(key);
+        info!("Bound {:?} to {:?}", key, action);
+        bindings.rebind(action, key);
+    } else if let Some(button) = button {
+        buttons.reset(button);
+        info!("Bound {:?} to {:?}", button.button_type, action);
+        bindings.rebind_button(action, button.button_type);
+    } else {
+        return;
+    }
+
+    // Move on to the next action until all of them are bound
+    let next = Action::ALL
+        .iter()
+        .position(|other| *other == action)
+        .unwrap()
+        + 1;
+    rebinding.0 = Action::ALL.get(next).copied();
+    prompt(rebinding.0);
+}
+
+fn prompt(action: Option<Action>) {
+    if let Some(action) = action {
+        info!("Press a key or button for {:?}", action);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    #[test]
+    fn a_key_or_button_only_triggers_one_action() {
+        let mut bindings = ActionBindings::defaults();
+        bindings.bind(Action::Attack, KeyCode::KeyE);
+        assert_eq!(
+            bindings.keys(Action::Attack),
+            &[KeyCode::Space, KeyCode::KeyE]
+        );
+        assert_eq!(bindings.keys(Action::Interact), &[]);
+
+        bindings.rebind(Action::MoveUp, KeyCode::KeyI);
+        assert_eq!(bindings.keys(Action::MoveUp), &[KeyCode::KeyI]);
+
+        bindings.bind_button(Action::Interact, GamepadButtonType::South);
+        assert_eq!(bindings.buttons(Action::Attack), &[GamepadButtonType::West]);
+        bindings.rebind_button(Action::Run, GamepadButtonType::LeftTrigger);
+        assert_eq!(
+            bindings.buttons(Action::Run),
+            &[GamepadButtonType::LeftTrigger]
+        );
+    }
+
+    #[test]
+    fn bindings_files_name_actions_keys_and_buttons() {
+        let bindings: ActionBindings = serde_json::from_str(
+            r#"{ "keys": { "Attack": ["Space", "KeyJ"] }, "buttons": { "Run": ["East"] } }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            bindings.keys(Action::Attack),
+            &[KeyCode::Space, KeyCode::KeyJ]
+        );
+        assert_eq!(bindings.buttons(Action::Run), &[GamepadButtonType::East]);
+        assert_eq!(bindings.keys(Action::MoveUp), &[]);
+        assert!(
+            serde_json::from_str::<ActionBindings>(r#"{ "keys": { "Jump": ["Space"] } }"#).is_err()
+        );
+    }
+
+    #[test]
+    fn bindings_files_take_their_keys_off_other_actions() {
+        let file: ActionBindings = serde_json::from_str(
+            r#"{ "keys": { "Attack": ["KeyE"], "Run": [] }, "buttons": { "Interact": ["South"] } }"#,
+        )
+        .unwrap();
+        let mut bindings = ActionBindings::defaults();
+        bindings.apply(&file);
+        assert_eq!(bindings.keys(Action::Attack), &[KeyCode::KeyE]);
+        assert_eq!(bindings.keys(Action::Interact), &[]);
+        assert_eq!(bindings.keys(Action::Run), &[]);
+        assert_eq!(
+            bindings.buttons(Action::Interact),
+            &[GamepadButtonType::South]
+        );
+        assert_eq!(bindings.buttons(Action::Attack), &[GamepadButtonType::West]);
+        assert_eq!(
+            bindings.keys(Action::MoveUp),
+            &[KeyCode::KeyW, KeyCode::ArrowUp]
+        );
+    }
+
+    fn world_with_input() -> World {
+        let mut world = World::new();
+        world.init_resource::<ButtonInput<KeyCode>>();
+        world.init_resource::<ButtonInput<GamepadButton>>();
+        world.init_resource::<Gamepads>();
+        world.init_resource::<Axis<GamepadAxis>>();
+        world.init_resource::<ButtonInput<Action>>();
+        world.init_resource::<MoveAxis>();
+        world.insert_resource(ActionBindings::defaults());
+        world.init_resource::<Rebinding>();
+        world
+    }
+
+    #[test]
+    fn actions_are_held_while_any_of_their_keys_is() {
+        let mut world = world_with_input();
+        world
+            .resource_mut::<ButtonInput<KeyCode>>()
+            .press(KeyCode::ArrowUp);
+        world.run_system_once(actions_system);
+        let actions = world.resource::<ButtonInput<Action>>();
+        assert!(actions.just_pressed(Action::MoveUp));
+        assert!(!actions.pressed(Action::MoveDown));
+
+        world.resource_mut::<ButtonInput<KeyCode>>().clear();
+        world.run_system_once(actions_system);
+        let actions = world.resource::<ButtonInput<Action>>();
+        assert!(actions.pressed(Action::MoveUp));
+        assert!(!actions.just_pressed(Action::MoveUp));
+
+        world
+            .resource_mut::<ButtonInput<KeyCode>>()
+            .release(KeyCode::ArrowUp);
+        world.run_system_once(actions_system);
+        assert!(world
+            .resource::<ButtonInput<Action>>()
+            .just_released(Action::MoveUp));
+    }
+
+    #[test]
+    fn move_actions_give_a_unit_move_axis() {
+        let mut world = world_with_input();
+        let mut keys = world.resource_mut::<ButtonInput<KeyCode>>();
+        keys.press(KeyCode::KeyW);
+        keys.press(KeyCode::KeyD);
+        world.run_system_once(actions_system);
+        let axis = world.resource::<MoveAxis>().0;
+        assert!((axis - Vec2::ONE.normalize()).length() < 1e-5);
+
+        // Left wins over right
+        world
+            .resource_mut::<ButtonInput<KeyCode>>()
+            .press(KeyCode::ArrowLeft);
+        world.run_system_once(actions_system);
+        let axis = world.resource::<MoveAxis>().0;
+        assert!((axis - Vec2::new(-1., 1.).normalize()).length() < 1e-5);
+    }
+
+    #[test]
+    fn the_left_stick_ignores_its_deadzone() {
+        let gamepad = Gamepad::new(0);
+        let mut axes = Axis::<GamepadAxis>::default();
+        let mut stick = |x: f32, y: f32| {
+            axes.set(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX), x);
+            axes.set(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickY), y);
+            left_stick(gamepad, &axes)
+        };
+        assert_eq!(stick(0.1, -0.1), Vec2::ZERO);
+        assert!((stick(0.6, 0.) - Vec2::new(0.5, 0.)).length() < 1e-5);
+        assert!((stick(0., -1.) - Vec2::new(0., -1.)).length() < 1e-5);
+    }
+
+    #[test]
+    fn rebinding_takes_the_next_key_or_button_pressed() {
+        let mut world = world_with_input();
+        world.resource_mut::<Rebinding>().0 = Some(Action::Attack);
+        world.run_system_once(rebind_system);
+        assert_eq!(world.resource::<Rebinding>().0, Some(Action::Attack));
+
+        world
+            .resource_mut::<ButtonInput<KeyCode>>()
+            .press(KeyCode::KeyJ);
+        world.run_system_once(rebind_system);
+        assert_eq!(world.resource::<Rebinding>().0, Some(Action::Interact));
+        assert_eq!(
+            world.resource::<ActionBindings>().keys(Action::Attack),
+            &[KeyCode::KeyJ]
+        );
+
+        world.resource_mut::<Rebinding>().0 = Some(Action::Run);
+        world.resource_mut::<ButtonInput<KeyCode>>().clear();
+        world
+            .resource_mut::<ButtonInput<GamepadButton>>()
+            .press(GamepadButton::new(
+                Gamepad::new(0),
+                GamepadButtonType::North,
+            ));
+        world.run_system_once(rebind_system);
+        assert_eq!(
+            world.resource::<ActionBindings>().buttons(Action::Run),
+            &[GamepadButtonType::North]
+        );
+    }
+
+    fn press(world: &mut World, key: KeyCode) {
+        world.resource_mut::<ButtonInput<KeyCode>>().press(key);
+        world.run_system_once(rebind_system);
+        world.run_system_once(actions_system);
+    }
+
+    #[test]
+    fn rebinding_uses_up_the_key_press() {
+        let mut world = world_with_input();
+        world.resource_mut::<Rebinding>().0 = Some(Action::Attack);
+
+        // Space was the attack key, J replaces it without attacking
+        press(&mut world, KeyCode::KeyJ);
+        let bindings = world.resource::<ActionBindings>();
+        assert_eq!(bindings.keys(Action::Attack), &[KeyCode::KeyJ]);
+        assert!(!world
+            .resource::<ButtonInput<Action>>()
+            .pressed(Action::Attack));
+        assert_eq!(world.resource::<Rebinding>().0, Some(Action::Interact));
+
+        // Space now only answers the prompt for Interact
+        press(&mut world, KeyCode::Space);
+        assert!(!world
+            .resource::<ButtonInput<Action>>()
+            .pressed(Action::Attack));
+        assert!(!world
+            .resource::<ButtonInput<Action>>()
+            .pressed(Action::Interact));
+        assert_eq!(world.resource::<Rebinding>().0, Some(Action::Pause));
+    }
+
+    #[test]
+    fn the_rebind_key_walks_through_every_action_and_stops() {
+        let mut world = world_with_input();
+        press(&mut world, REBIND_KEY);
+        assert_eq!(world.resource::<Rebinding>().0, Some(Action::MoveUp));
+        world.resource_mut::<ButtonInput<KeyCode>>().clear();
+        press(&mut world, REBIND_KEY);
+        assert_eq!(world.resource::<Rebinding>().0, None);
+        assert_eq!(
+            world.resource::<ActionBindings>().keys(Action::MoveUp),
+            &[KeyCode::KeyW, KeyCode::ArrowUp]
+        );
+
+        // Binding the last action finishes
+        world.resource_mut::<Rebinding>().0 = Some(Action::Run);
+        press(&mut world, KeyCode::KeyR);
+        assert_eq!(world.resource::<Rebinding>().0, None);
+        assert_eq!(
+            world.resource::<ActionBindings>().keys(Action::Run),
+            &[KeyCode::KeyR]
+        );
+    }
+}
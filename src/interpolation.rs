@@ -0,0 +1,58 @@
+use crate::components::Interpolated;
+use crate::constants::FIXED_STEP_HZ;
+use bevy::{prelude::*, transform::TransformSystem};
+
+// Gameplay moves things in fixed steps in FixedUpdate, which can run zero or several times
+// in a frame. Interpolated entities are drawn between their last two steps so they move
+// smoothly at any frame rate, while every other system still sees where they really are.
+pub struct InterpolationPlugin;
+
+impl Plugin for InterpolationPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Time::<Fixed>::from_hz(FIXED_STEP_HZ))
+            .add_systems(First, restore_translation_system)
+            .add_systems(FixedFirst, store_previous_translation_system)
+            .add_systems(FixedLast, store_current_translation_system)
+            .add_systems(
+                PostUpdate,
+                interpolate_translation_system.before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+// Put back the translation of the last step before anything reads it
+fn restore_translation_system(mut query: Query<(&mut Transform, &Interpolated)>) {
+    for (mut transform, interpolated) in query.iter_mut() {
+        transform.translation = interpolated.current;
+    }
+}
+
+fn store_previous_translation_system(mut query: Query<(&Transform, &mut Interpolated)>) {
+    for (transform, mut interpolated) in query.iter_mut() {
+        interpolated.previous = transform.translation;
+    }
+}
+
+fn store_current_translation_system(mut query: Query<(&Transform, &mut Interpolated)>) {
+    for (transform, mut interpolated) in query.iter_mut() {
+        interpolated.current = transform.translation;
+    }
+}
+
+// Draw each entity part of the way from its previous step to its current one, by how far
+// the clock has run ahead of the last step
+pub fn interpolate_translation_system(
+    time: Res<Time<Fixed>>,
+    mut query: Query<(&mut Transform, &mut Interpolated)>,
+) {
+    let alpha = time.overstep_fraction();
+    for (mut transform, mut interpolated) in query.iter_mut() {
+        // Entities that were just spawned or moved outside of a step (e.g. put on a spawn
+        // point) jump there instead of sliding across the map
+        if transform.translation != interpolated.current {
+            interpolated.previous = transform.translation;
+            interpolated.current = transform.translation;
+        }
+        transform.translation = interpolated.previous.lerp(interpolated.current, alpha);
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::HashSet;
+
+use crate::collision::CollisionStarted;
+use crate::components::{Impulse, LevelExit, MapRoot, Player, Projectile, Velocity};
+use crate::objects::ObjectRegistry;
+use crate::resources::CurrentMap;
+use crate::tilemap::{spawn_map, TileMap};
+use crate::AppState;
+use bevy::{asset::LoadState, prelude::*};
+
+pub struct LevelPlugin;
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LevelManager>().add_systems(
+            Update,
+            (level_exit_system, level_load_system)
+                .chain()
+                .run_if(in_state(AppState::InGame)),
+        );
+    }
+}
+
+// Tracks a map change that has been requested but not finished yet
+#[derive(Resource, Default)]
+pub struct LevelManager {
+    pending: Option<PendingLevel>,
+    failed: HashSet<String>, // Maps that could not be loaded, their exits stay closed
+}
+
+struct PendingLevel {
+    destination: String,
+    spawn_point: Option<String>,
+    previous: Handle<TileMap>, // Restored if the new map fails to load
+    returning: bool,           // Going back to the previous map, the player stays where it was
+}
+
+impl LevelManager {
+    pub fn is_loading(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    // Unload the current map and start loading another one. The player entity is not part
+    // of the map, so it keeps all of its state and is only moved once the new map is in.
+    // Projectiles still in flight belong to the old map and go with it.
+    #[allow(clippy::type_complexity)]
+    pub fn change_level(
+        &mut self,
+        commands: &mut Commands,
+        asset_server: &AssetServer,
+        current_map: &mut CurrentMap,
+        unload_query: &Query<Entity, Or<(With<MapRoot>, With<Projectile>)>>,
+        destination: &str,
+        spawn_point: Option<String>,
+    ) {
+        for entity in unload_query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        let previous = std::mem::replace(
+            &mut current_map.0,
+            asset_server.load(destination.to_string()),
+        );
+        self.pending = Some(PendingLevel {
+            destination: destination.to_string(),
+            spawn_point,
+            previous,
+            returning: false,
+        });
+        info!("Loading level {}", destination);
+    }
+}
+
+// Start a level change when the player steps into an exit
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn level_exit_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut level_manager: ResMut<LevelManager>,
+    mut current_map: ResMut<CurrentMap>,
+    unload_query: Query<Entity, Or<(With<MapRoot>, With<Projectile>)>>,
+    mut events: EventReader<CollisionStarted>,
+    player_query: Query<Entity, With<Player>>,
+    exit_query: Query<&LevelExit>,
+) {
+    if level_manager.is_loading() {
+        events.clear();
+        return;
+    }
+    let Ok(player) = player_query.get_single() else {
+        return;
+    };
+
+    for event in events.read() {
+        let Some(exit) = event
+            .other(player)
+            .and_then(|other| exit_query.get(other).ok())
+        else {
+            continue;
+        };
+        let Some(destination) = &exit.destination else {
+            continue;
+        };
+        if level_manager.failed.contains(destination) {
+            continue;
+        }
+
+        level_manager.change_level(
+            &mut commands,
+            &asset_server,
+            &mut current_map,
+            &unload_query,
+            destination,
+            exit.spawn_point.clone(),
+        );
+        return;
+    }
+}
+
+// Spawn the requested map once it has loaded and put the player on its spawn point. The
+// game is paused meanwhile, since there are no walls until the map is in.
+#[allow(clippy::too_many_arguments)]
+fn level_load_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut level_manager: ResMut<LevelManager>,
+    mut current_map: ResMut<CurrentMap>,
+    maps: Res<Assets<TileMap>>,
+    registry: Res<ObjectRegistry>,
+    mut time: ResMut<Time<Virtual>>,
+    mut player_query: Query<(&mut Transform, &mut Velocity, &mut Impulse), With<Player>>,
+) {
+    let LevelManager { pending, failed } = &mut *level_manager;
+    let Some(pending) = pending else {
+        return;
+    };
+    time.pause();
+
+    // The asset server reports why the map failed, go back to the map we came from
+    if asset_server.load_state(&current_map.0) == LoadState::Failed {
+        error!("Could not load level {:?}", current_map.0.path());
+        failed.insert(pending.destination.clone());
+        current_map.0 = pending.previous.clone();
+        pending.returning = true;
+        return;
+    }
+    if !asset_server.is_loaded_with_dependencies(&current_map.0) {
+        return;
+    }
+    let Some(map) = maps.get(&current_map.0) else {
+        return;
+    };
+
+    spawn_map(&mut commands, map, &registry, &asset_server);
+    time.unpause();
+    if pending.returning {
+        level_manager.pending = None;
+        return;
+    }
+
+    let spawn_point = pending.spawn_point.as_deref();
+    let start = map.spawn_point(spawn_point).unwrap_or_else(|| {
+        warn!("Map has no spawn point {:?}, using its center", spawn_point);
+        map.center()
+    });
+    if let Ok((mut transform, mut velocity, mut impulse)) = player_query.get_single_mut() {
+        transform.translation.x = start.x;
+        transform.translation.y = start.y;
+        velocity.x = 0.;
+        velocity.y = 0.;
+        impulse.0 = Vec2::ZERO;
+    }
+
+    level_manager.pending = None;
+}
@@ -1,8 +1,8 @@
 #![allow(unused)] // Silence warning for unused code while exploring
 
+use std::collections::HashSet;
 use std::iter;
 
-use ascii::AsciiPlugin;
 use bevy::{
     asset::LoadedFolder, 
     ecs::query, 
@@ -12,17 +12,39 @@ use bevy::{
     render::texture::ImageSampler, 
     transform::commands, ui::update
 };
-use components::{Direction, Movable, Player, TileCollider, Velocity};
+use collision::{CollisionPlugin, CollisionStarted, Collisions, TileColliders};
+use components::{
+    Collider, CollisionLayers, Direction, Impulse, Locomotion, Movable, MoveIntent, Player,
+    Projectile, Sensor, TileCollider, Velocity,
+};
 use constants::*;
+use enemy::EnemyPlugin;
+use input::{Action, ActionsPlugin};
+use interpolation::{interpolate_translation_system, InterpolationPlugin};
+use level::{LevelManager, LevelPlugin};
+use pickup::PickupPlugin;
 use player::PlayerPlugin;
-use resources::{GameTextures, RpgSpriteFolder, WinSize};
-use tilemap::TileMapPlugin;
+use resources::{CurrentMap, GameTextures, RpgSpriteFolder, WinSize};
+use chunks::chunk_streaming_system;
+use tilemap::{TileMap, TileMapPlugin};
 
 pub mod ascii;
+mod autotile;
+mod chunks;
+mod collision;
 pub mod components; // Needs to be made public so other files can use it!
 pub mod constants;
+mod dungeon;
+mod enemy;
+mod input;
+mod interpolation;
+mod level;
+pub mod objects;
+mod pickup;
 mod player;
 pub mod resources;
+mod tile_mesh;
+mod tiled;
 mod tilemap;
 
 #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, States)]
@@ -45,15 +67,35 @@ fn main() {
         }))
         .init_state::<AppState>()
         .add_plugins(PlayerPlugin)
-        .add_plugins(AsciiPlugin)
+        .add_plugins(EnemyPlugin)
+        .add_plugins(PickupPlugin)
         .add_plugins(TileMapPlugin)
+        .add_plugins(LevelPlugin)
+        .add_plugins(CollisionPlugin)
+        .add_plugins(InterpolationPlugin)
+        .add_plugins(ActionsPlugin)
         .add_systems(OnEnter(AppState::Preload), load_player_sprites)
         .add_systems(OnEnter(AppState::Preload), get_winsize)
         .add_systems(Update, check_textures.run_if(in_state(AppState::Preload)))
         .add_systems(OnEnter(AppState::Setup), setup)
+        .add_systems(
+            FixedUpdate,
+            (velocity_system, movable_system)
+                .chain()
+                .run_if(in_state(AppState::InGame)),
+        )
         .add_systems(
             Update,
-            (movable_system, sprite_flip_system).run_if(in_state(AppState::InGame)),
+            (projectile_hit_system, sprite_flip_system, pause_system)
+                .run_if(in_state(AppState::InGame)),
+        )
+        .add_systems(
+            PostUpdate,
+            // Follows where the player is drawn, and chunks are streamed around the result
+            camera_follow_system
+                .after(interpolate_translation_system)
+                .before(chunk_streaming_system)
+                .run_if(in_state(AppState::InGame)),
         )
         .run();
 }
@@ -134,15 +176,17 @@ fn create_texture_atlas(
 fn check_textures(
     mut next_state: ResMut<NextState<AppState>>,
     game_textures: Res<GameTextures>,
-    mut events: EventReader<AssetEvent<LoadedFolder>>,
+    current_map: Res<CurrentMap>,
+    asset_server: Res<AssetServer>,
 ) {
-    // Advance the AppState once all the sprite handles have been loaded by the asset server
-    for event in events.read() {
-        for rpg_sprite_folder in game_textures.player_folders.iter() {
-            if event.is_loaded_with_dependencies(rpg_sprite_folder) {
-                next_state.set(AppState::Setup)
-            }
-        }
+    // Advance the AppState once all the sprite folders and the map have been loaded by the asset server.
+    // A map that fails to parse is reported by the asset server and keeps us here instead of panicking.
+    let folders_loaded = game_textures
+        .player_folders
+        .iter()
+        .all(|folder| asset_server.is_loaded_with_dependencies(folder));
+    if folders_loaded && asset_server.is_loaded_with_dependencies(&current_map.0) {
+        next_state.set(AppState::Setup)
     }
 }
 
@@ -168,42 +212,178 @@ fn create_sprite_from_atlas(
     });
 }
 
+// Speed movers up towards where they are trying to go and slow them down when they stop
+// trying, then add any impulses they were given. Slippery ground makes both take longer.
+#[allow(clippy::type_complexity)]
+fn velocity_system(
+    time: Res<Time>,
+    map: Option<Res<TileMap>>,
+    mut query: Query<(
+        &mut Velocity,
+        &Transform,
+        Option<&Locomotion>,
+        Option<&MoveIntent>,
+        Option<&mut Impulse>,
+    )>,
+) {
+    for (mut velocity, transform, locomotion, intent, impulse) in query.iter_mut() {
+        let mut current = Vec2::new(velocity.x, velocity.y);
+
+        // Movers without locomotion (e.g. projectiles) keep their speed
+        if let Some(locomotion) = locomotion {
+            let intent = intent.map_or(Vec2::ZERO, |intent| intent.clamp_length_max(1.));
+            let target = intent * locomotion.max_speed;
+            let rate = if intent == Vec2::ZERO {
+                locomotion.friction
+            } else {
+                locomotion.acceleration
+            };
+            let traction = map.as_ref().map_or(1., |map| {
+                map.traction_at(transform.translation.truncate())
+            });
+            let step = rate * traction * time.delta_seconds();
+            let change = target - current;
+            current += change.clamp_length_max(step);
+        }
+
+        if let Some(mut impulse) = impulse {
+            current += impulse.0;
+            impulse.0 = Vec2::ZERO;
+        }
+
+        velocity.x = current.x;
+        velocity.y = current.y;
+    }
+}
+
 // For every velocity and transform component together with a player component, change the player position (i.e. translation) based on the updated velocity
+#[allow(clippy::type_complexity)]
 fn movable_system(
     mut commands: Commands,
+    time: Res<Time>,
     win_size: Res<WinSize>,
-    mut query: Query<(Entity, &Velocity, &mut Transform, &Movable), Without<TileCollider>>,
-    mut wall_query: Query<&Transform, (With<TileCollider>, Without<Player>)>
+    mut query: Query<
+        (
+            Entity,
+            &Velocity,
+            &mut Transform,
+            &Movable,
+            Option<&Collider>,
+            Option<&CollisionLayers>,
+            Has<Sensor>,
+            Has<Projectile>,
+        ),
+        Without<TileCollider>,
+    >,
+    camera_query: Query<&Transform, (With<Camera2d>, Without<Movable>)>,
+    walls: TileColliders,
+    mut collisions: ResMut<Collisions>,
 ) {
+    // Things that despawn off screen are measured from the camera
+    let camera = camera_query
+        .get_single()
+        .map_or(Vec2::ZERO, |camera_tf| camera_tf.translation.truncate());
+
     // only '&' for read-only access. '&mut' for read-write access
-    for (entity, velocity, mut transform, movable) in query.iter_mut() {
+    for (entity, velocity, mut transform, movable, collider, layers, sensor, projectile) in
+        query.iter_mut()
+    {
         // iter_mut() because we're going to mutate the transform
+        // Sensors pass through walls
+        let collider = collider.filter(|_| !sensor);
+        let layers = layers.unwrap_or(&CollisionLayers::ALL);
+
         // Apply velocity to get target position
-        let x_delta = velocity.x * TIME_STEP * BASE_SPEED;
-        let x_target = transform.translation + Vec3::new(x_delta, 0.0, 0.0);
-        if !collision_check_system(x_target, &wall_query) {
-            transform.translation.x += x_delta;
+        let delta = Vec2::new(velocity.x, velocity.y) * time.delta_seconds();
+        let position = transform.translation.truncate();
+        let (position, hits) = match collider {
+            // Projectiles are used up by the first wall they hit instead of sliding along it
+            Some(collider) if projectile => {
+                let (position, hit) = walls.move_until_hit(position, delta, collider, layers);
+                if hit.is_some() {
+                    commands.entity(entity).despawn();
+                    continue;
+                }
+                (position, Vec::new())
+            }
+            Some(collider) => walls.move_and_slide(position, delta, collider, layers),
+            None => (position + delta, Vec::new()),
         };
+        transform.translation = position.extend(transform.translation.z);
+        for hit in hits {
+            collisions.block(entity, hit.entity);
+        }
 
-        let y_delta = velocity.y * TIME_STEP * BASE_SPEED;
-        let y_target = transform.translation + Vec3::new(0.0, y_delta, 0.0);
-        if !collision_check_system(y_target, &wall_query) {
-            transform.translation.y += y_delta;
-        };
+        // translation.x += velocity.x * time.delta_seconds();
+        // translation.y += velocity.y * time.delta_seconds();
 
-        // translation.x += velocity.x * TIME_STEP * BASE_SPEED;
-        // translation.y += velocity.y * TIME_STEP * BASE_SPEED;
+        let offset = position - camera;
+        if movable.auto_despawn
+            && (offset.y > win_size.h / 2. + MARGIN
+                || offset.y < -win_size.h / 2. - MARGIN
+                || offset.x > win_size.w / 2. + MARGIN
+                || offset.x < -win_size.w / 2. - MARGIN)
+        {
+            commands.entity(entity).despawn();
+        }
+    }
+}
 
-        if movable.auto_despawn {
-            if transform.translation.y > win_size.h / 2. + MARGIN
-                || transform.translation.y < -win_size.h / 2. - MARGIN
-                || transform.translation.x > win_size.w / 2. + MARGIN
-                || transform.translation.x < -win_size.w / 2. - MARGIN
-            {
-                commands.entity(entity).despawn();
+// Keep the camera centered on the player
+fn camera_follow_system(
+    player_query: Query<&Transform, With<Player>>,
+    mut camera_query: Query<&mut Transform, (With<Camera2d>, Without<Player>)>,
+) {
+    let (Ok(player_tf), Ok(mut camera_tf)) =
+        (player_query.get_single(), camera_query.get_single_mut())
+    else {
+        return;
+    };
+    camera_tf.translation.x = player_tf.translation.x;
+    camera_tf.translation.y = player_tf.translation.y;
+}
+
+// Projectiles are used up by the first thing they hit
+fn projectile_hit_system(
+    mut commands: Commands,
+    mut events: EventReader<CollisionStarted>,
+    projectile_query: Query<(), With<Projectile>>,
+    sensor_query: Query<(), With<Sensor>>,
+) {
+    // A projectile can hit several things in the same frame but is only despawned once
+    let mut spent: HashSet<Entity> = HashSet::new();
+    for event in events.read() {
+        let (a, b) = event.entities();
+        for (entity, other) in [(a, b), (b, a)] {
+            if projectile_query.contains(entity) && !sensor_query.contains(other) {
+                spent.insert(entity);
             }
         }
     }
+    for entity in spent {
+        commands.entity(entity).despawn();
+    }
+}
+
+// Stopping the virtual clock stops the fixed steps, so nothing moves until it restarts.
+// Level changes pause the game themselves and unpause it once the new map is in.
+fn pause_system(
+    actions: Res<ButtonInput<Action>>,
+    level_manager: Res<LevelManager>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    if actions.just_pressed(Action::Pause) && !level_manager.is_loading() {
+        if time.is_paused() {
+            time.unpause();
+        } else {
+            time.pause();
+        }
+    }
+}
+
+// Run condition for systems that act on player input, which is ignored while paused
+pub fn game_running(time: Res<Time<Virtual>>) -> bool {
+    !time.is_paused()
 }
 
 fn sprite_flip_system(mut query: Query<(&mut Sprite, &Direction), With<Movable>>) {
@@ -215,33 +395,114 @@ fn sprite_flip_system(mut query: Query<(&mut Sprite, &Direction), With<Movable>>
     }
 }
 
-fn collision_check_system(
-    target_player_pos: Vec3,
-    wall_query: &Query<&Transform, (With<TileCollider>, Without<Player>)>
-) -> bool {
-    for wall_transform in wall_query.iter() {
-        
-        // Get Aabb2d of wall and player
-        let wall_rect = Aabb2d::new(
-            wall_transform.translation.truncate(),
-            Vec2::splat(TILE_SIZE / 2.)
-        );
-        let player_rect = Aabb2d::new(
-            target_player_pos.truncate(),
-            Vec2::new(6., 11.)
-        );
-
-        let collision = player_rect.intersects(&wall_rect);
-        println!("Collision: {}", collision);
-        return collision
-    }
-
-    false
-}
-
 // System to print keyboard events as they come in
 fn print_keyboard_events(mut keyboard_events: EventReader<KeyboardInput>) {
     for event in keyboard_events.read() {
         println!("{:?}", event);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::tilemap::{Tile, TileLayer, Tileset};
+
+    // A map covered in one ground tile, with the given traction if it isn't plain ground
+    fn world_with_ground(traction: Option<f32>) -> World {
+        let mut map = TileMap::new(4, 4, Vec2::splat(32.));
+        map.tilesets.push(Tileset {
+            name: "ground".to_string(),
+            texture: Handle::default(),
+            layout: Handle::default(),
+            animations: HashMap::new(),
+            autotiles: HashMap::new(),
+            traction: traction.map(|traction| (0, traction)).into_iter().collect(),
+        });
+        let ground = Tile::new(0, 0);
+        map.layers.push(TileLayer {
+            name: "ground".to_string(),
+            tiles: vec![Some(ground); 16],
+            visible: true,
+            opacity: 1.,
+        });
+
+        let mut world = World::new();
+        // Every run of the velocity system is one 60 Hz step
+        let mut time = Time::<()>::default();
+        time.advance_by(Duration::from_secs_f64(1. / 60.));
+        world.insert_resource(time);
+        world.insert_resource(map);
+        world
+    }
+
+    fn spawn_walker(world: &mut World, intent: Vec2) -> Entity {
+        world
+            .spawn((
+                Transform::default(),
+                Velocity { x: 0., y: 0. },
+                Locomotion {
+                    max_speed: 120.,
+                    acceleration: 600.,
+                    friction: 1200.,
+                },
+                MoveIntent(intent),
+                Impulse::default(),
+            ))
+            .id()
+    }
+
+    fn speed(world: &World, entity: Entity) -> f32 {
+        let velocity = world.get::<Velocity>(entity).unwrap();
+        Vec2::new(velocity.x, velocity.y).length()
+    }
+
+    fn step(world: &mut World, steps: usize) {
+        for _ in 0..steps {
+            world.run_system_once(velocity_system);
+        }
+    }
+
+    #[test]
+    fn movers_speed_up_to_their_max_speed_and_slow_to_a_stop() {
+        let mut world = world_with_ground(None);
+        let mover = spawn_walker(&mut world, Vec2::new(1., 1.));
+
+        // 10 pixels per second faster every step, diagonals are no faster
+        step(&mut world, 1);
+        assert!((speed(&world, mover) - 10.).abs() < 1e-3);
+        step(&mut world, 20);
+        assert!((speed(&world, mover) - 120.).abs() < 1e-3);
+
+        // Impulses are added once
+        world.get_mut::<Impulse>(mover).unwrap().0 = Vec2::new(-500., 0.);
+        world.get_mut::<MoveIntent>(mover).unwrap().0 = Vec2::ZERO;
+        step(&mut world, 1);
+        assert_eq!(world.get::<Impulse>(mover).unwrap().0, Vec2::ZERO);
+        step(&mut world, 30);
+        assert_eq!(speed(&world, mover), 0.);
+    }
+
+    #[test]
+    fn movers_on_ice_speed_up_and_slow_down_slower() {
+        let mut world = world_with_ground(Some(0.25));
+        let mover = spawn_walker(&mut world, Vec2::X);
+
+        // A quarter of the grip, a quarter of the acceleration
+        step(&mut world, 1);
+        assert!((speed(&world, mover) - 2.5).abs() < 1e-3);
+        step(&mut world, 47);
+        assert!((speed(&world, mover) - 120.).abs() < 1e-3);
+
+        // Stopping from full speed takes 24 steps instead of 6
+        world.get_mut::<MoveIntent>(mover).unwrap().0 = Vec2::ZERO;
+        step(&mut world, 23);
+        assert!((speed(&world, mover) - 5.).abs() < 1e-3);
+        step(&mut world, 1);
+        assert_eq!(speed(&world, mover), 0.);
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+
+use bevy::{ecs::system::EntityCommands, prelude::*};
+
+use crate::components::ObjectProperties;
+use crate::tilemap::MapObject;
+
+// A spawn function fills in the components for one map object type. The entity it
+// receives already has a transform at the object's position and its ObjectProperties.
+pub type ObjectSpawner = fn(&mut EntityCommands, &MapObject, &AssetServer);
+
+// Maps a map object `type` (from Tiled or another map format) to the function that spawns it
+#[derive(Resource, Default)]
+pub struct ObjectRegistry {
+    spawners: HashMap<String, ObjectSpawner>,
+}
+
+impl ObjectRegistry {
+    pub fn register(&mut self, kind: &str, spawner: ObjectSpawner) {
+        if self.spawners.insert(kind.to_string(), spawner).is_some() {
+            warn!("Map object type '{}' was registered twice", kind);
+        }
+    }
+
+    // Spawn an object at the given translation, or None if nobody registered its type
+    pub fn spawn(
+        &self,
+        commands: &mut Commands,
+        asset_server: &AssetServer,
+        object: &MapObject,
+        translation: Vec3,
+    ) -> Option<Entity> {
+        let Some(spawner) = self.spawners.get(&object.kind) else {
+            warn!(
+                "No spawner registered for map object type '{}'",
+                object.kind
+            );
+            return None;
+        };
+
+        let mut entity = commands.spawn((
+            SpatialBundle::from_transform(Transform::from_translation(translation)),
+            Name::new(object.kind.clone()),
+            ObjectProperties(object.properties.clone()),
+        ));
+        spawner(&mut entity, object, asset_server);
+        Some(entity.id())
+    }
+}
+
+// Lets each plugin register the map objects it knows how to spawn
+pub trait MapObjectAppExt {
+    fn register_map_object(&mut self, kind: &str, spawner: ObjectSpawner) -> &mut Self;
+}
+
+impl MapObjectAppExt for App {
+    fn register_map_object(&mut self, kind: &str, spawner: ObjectSpawner) -> &mut Self {
+        self.init_resource::<ObjectRegistry>();
+        self.world
+            .resource_mut::<ObjectRegistry>()
+            .register(kind, spawner);
+        self
+    }
+}
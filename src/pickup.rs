@@ -0,0 +1,64 @@
+use crate::collision::CollisionStarted;
+use crate::components::{Collider, CollisionLayers, Layer, Pickup, Player, Sensor};
+use crate::constants::{COIN_SPRITE, GEM_SPRITE};
+use crate::objects::MapObjectAppExt;
+use crate::tilemap::MapObject;
+use crate::AppState;
+use bevy::{
+    ecs::system::EntityCommands,
+    prelude::*,
+    render::texture::{ImageLoaderSettings, ImageSampler},
+};
+
+pub struct PickupPlugin;
+
+impl Plugin for PickupPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_map_object("pickup", spawn_pickup)
+            .add_systems(Update, pickup_system.run_if(in_state(AppState::InGame)));
+    }
+}
+
+// Map objects of type "pickup" hold the item named by their "item" property, coins by default
+fn spawn_pickup(entity: &mut EntityCommands, object: &MapObject, asset_server: &AssetServer) {
+    let item = object.property_str("item").unwrap_or("coin");
+    let path = match item {
+        "gem" => GEM_SPRITE,
+        _ => COIN_SPRITE,
+    };
+    let texture: Handle<Image> = asset_server
+        .load_with_settings(path, |settings: &mut ImageLoaderSettings| {
+            settings.sampler = ImageSampler::nearest()
+        });
+    entity.insert((
+        Sprite::default(),
+        texture,
+        Pickup {
+            item: item.to_string(),
+        },
+        Collider::circle(5.),
+        CollisionLayers::new([Layer::Pickup], [Layer::Player]),
+        Sensor,
+    ));
+}
+
+// Collect the pickups the player walks into
+fn pickup_system(
+    mut commands: Commands,
+    mut events: EventReader<CollisionStarted>,
+    player_query: Query<Entity, With<Player>>,
+    pickup_query: Query<&Pickup>,
+) {
+    let Ok(player) = player_query.get_single() else {
+        return;
+    };
+    for event in events.read() {
+        let Some(entity) = event.other(player) else {
+            continue;
+        };
+        if let Ok(pickup) = pickup_query.get(entity) {
+            info!("Picked up {}", pickup.item);
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
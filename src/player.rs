@@ -1,10 +1,22 @@
-use crate::components::{AnimationIndices, AnimationTimer, Direction, Movable, Player, TileCollider, Velocity};
-use crate::constants::{BASE_SPEED, LASER_SCALE, PLAYER_SIZE, TIME_STEP};
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+
+use crate::collision::CollisionStarted;
+use crate::input::{Action, MoveAxis};
+use crate::components::{
+    AnimationIndices, AnimationTimer, Collider, CollisionLayers, Direction, Enemy, Facing,
+    Impulse, Interpolated, Layer, Locomotion, Movable, MoveIntent, Player, Projectile,
+    TileCollider, Velocity,
+};
+use crate::constants::{
+    LASER_SCALE, LASER_SIZE, LASER_SPEED, PLAYER_ACCELERATION, PLAYER_FRICTION, PLAYER_HALF_EXTENTS,
+    PLAYER_KNOCKBACK, PLAYER_RUN_SPEED, PLAYER_SIZE, PLAYER_SPEED,
+};
 use crate::resources::{GameTextures, WinSize};
-use crate::{create_sprite_from_atlas, create_texture_atlas, collision_check_system, AppState};
+use crate::tilemap::TileMap;
+use crate::{create_sprite_from_atlas, create_texture_atlas, game_running, AppState};
 use bevy::{
-    asset::io::gated::GateOpener, asset::LoadedFolder, ecs::query, prelude::*, render::texture,
-    render::texture::ImageSampler,
+    asset::io::gated::GateOpener, asset::LoadedFolder, ecs::query,
+    prelude::*, render::texture, render::texture::ImageSampler,
 };
 
 
@@ -15,21 +27,24 @@ impl Plugin for PlayerPlugin {
         app.add_systems(OnEnter(AppState::InGame), player_spawn_system)
             .add_systems(
                 Update,
-                player_keyboard_event_system.run_if(in_state(AppState::InGame)),
+                player_input_system.run_if(in_state(AppState::InGame).and_then(game_running)),
             )
             .add_systems(
                 Update,
-                player_fire_system.run_if(in_state(AppState::InGame)),
+                player_fire_system.run_if(in_state(AppState::InGame).and_then(game_running)),
             )
             .add_systems(
                 Update,
+                player_knockback_system.run_if(in_state(AppState::InGame)),
+            )
+            .add_systems(
+                FixedUpdate,
                 player_animation_system.run_if(in_state(AppState::InGame)),
             )
             .add_systems(
                 Update,
                 update_player_animation_texture_system.run_if(in_state(AppState::InGame)),
             );
-            //.add_systems(Update, player_tile_collision_system);
     }
 }
 
@@ -39,11 +54,10 @@ fn player_spawn_system(
     mut game_textures: ResMut<GameTextures>,
     mut texture_atlases: ResMut<Assets<TextureAtlasLayout>>,
     mut textures: ResMut<Assets<Image>>,
-    asset_server: Res<AssetServer>,
-    win_size: Res<WinSize>,
+    map: Res<TileMap>,
 ) {
-    // Get the window size
-    let bottom = -win_size.h / 2.;
+    // Start on the map's player start if it has one, otherwise in the middle of the map
+    let start = map.spawn_point(None).unwrap_or_else(|| map.center());
 
     // Create Player texture atlas
     for i in 0..game_textures.player_folders.len() {
@@ -69,7 +83,7 @@ fn player_spawn_system(
         .spawn((
             SpriteSheetBundle {
                 transform: Transform {
-                    translation: Vec3::new(0., bottom + PLAYER_SIZE.1 / 2., 0.),
+                    translation: start.extend(0.),
                     ..default()
                 },
                 texture: game_textures.player_textures[0].clone(),
@@ -87,11 +101,31 @@ fn player_spawn_system(
             AnimationTimer(Timer::from_seconds(0.1, TimerMode::Repeating)),
         ))
         .insert(Player)
+        .insert(Collider::aabb(PLAYER_HALF_EXTENTS))
+        .insert(CollisionLayers::new(
+            [Layer::Player],
+            [
+                Layer::Enemy,
+                Layer::EnemyProjectile,
+                Layer::Wall,
+                Layer::Water,
+                Layer::Pickup,
+            ],
+        ))
         .insert(Velocity { x: 0., y: 0. })
+        .insert(Locomotion {
+            max_speed: PLAYER_SPEED,
+            acceleration: PLAYER_ACCELERATION,
+            friction: PLAYER_FRICTION,
+        })
+        .insert(MoveIntent::default())
+        .insert(Impulse::default())
         .insert(Movable {
             auto_despawn: false,
         })
-        .insert(Direction::Down);
+        .insert(Interpolated::default())
+        .insert(Direction::Down)
+        .insert(Facing(Vec2::NEG_Y));
 }
 
 // Use animation textures if moving
@@ -126,6 +160,7 @@ fn player_animation_system(
 }
 
 // Update the TextureAtlasLayout and animation indices depending on direction
+#[allow(clippy::type_complexity)]
 fn update_player_animation_texture_system(
     mut query: Query<(
             &mut AnimationIndices,
@@ -206,22 +241,26 @@ fn update_player_animation_texture_system(
 // Fire the laser (or swing the weapon, will update later)
 fn player_fire_system(
     mut commands: Commands,
-    kb: Res<ButtonInput<KeyCode>>,
+    actions: Res<ButtonInput<Action>>,
     game_textures: Res<GameTextures>,
-    query: Query<(&Transform, &Direction), With<Player>>,
+    query: Query<(&Transform, &Facing), With<Player>>,
 ) {
-    if let Ok((player_tf, player_dir)) = query.get_single() {
-        if kb.just_pressed(KeyCode::Space) {
+    if let Ok((player_tf, facing)) = query.get_single() {
+        if actions.just_pressed(Action::Attack) {
             // Get player location
             let (x, y) = (player_tf.translation.x, player_tf.translation.y);
 
-            // Get player direction
-            let (dx, dy, rot): (f32, f32, f32) = match player_dir {
-                Direction::Up => (0., 1., 0.),
-                Direction::Down => (0., -1., 180.),
-                Direction::Left => (-1., 0., 90.),
-                Direction::Right => (1., 0., -90.),
-            };
+            // Get player direction, the laser sprite points up
+            let (dx, dy) = (facing.x, facing.y);
+            let rot = dy.atan2(dx) - FRAC_PI_2;
+
+            // The hitbox is the box around the turned laser
+            let laser_extents = Vec2::new(LASER_SIZE.0, LASER_SIZE.1) * LASER_SCALE / 2.;
+            let (sin, cos) = (rot.sin().abs(), rot.cos().abs());
+            let half_extents = Vec2::new(
+                cos * laser_extents.x + sin * laser_extents.y,
+                sin * laser_extents.x + cos * laser_extents.y,
+            );
 
             // Spawn laser at player location with proper direction and velocity
             commands
@@ -229,60 +268,84 @@ fn player_fire_system(
                     texture: game_textures.player_laser.clone(),
                     transform: Transform {
                         translation: Vec3::new(x, y, 0.),
-                        rotation: Quat::from_rotation_z(rot.to_radians()),
+                        rotation: Quat::from_rotation_z(rot),
                         scale: Vec3::new(LASER_SCALE, LASER_SCALE, 0.),
-                        ..default()
                     },
                     ..default()
                 })
+                .insert(Collider::aabb(half_extents))
+                .insert(CollisionLayers::new(
+                    [Layer::PlayerProjectile],
+                    [Layer::Enemy, Layer::Wall],
+                ))
+                .insert(Projectile)
                 .insert(Velocity {
-                    x: 2. * dx,
-                    y: 2. * dy,
+                    x: LASER_SPEED * dx,
+                    y: LASER_SPEED * dy,
                 })
-                .insert(Movable { auto_despawn: true });
+                .insert(Movable { auto_despawn: true })
+                .insert(Interpolated::default());
         }
     }
 }
 
-// For every velocity component and direction component with the player component,
-// change the velocity and direction based on keyboard input
-fn player_keyboard_event_system(
-    kb: Res<ButtonInput<KeyCode>>,
-    mut query: Query<(&mut Velocity, &mut Direction), With<Player>>
+// For every move intent, locomotion, facing and direction component with the player
+// component, change where the player is trying to go, how fast and where it looks based on
+// the input actions
+#[allow(clippy::type_complexity)]
+fn player_input_system(
+    actions: Res<ButtonInput<Action>>,
+    move_axis: Res<MoveAxis>,
+    mut query: Query<
+        (&mut MoveIntent, &mut Locomotion, &mut Facing, &mut Direction),
+        With<Player>,
+    >,
 ) {
-    if let Ok((mut velocity, mut direction)) = query.get_single_mut() {
+    if let Ok((mut intent, mut locomotion, mut facing, mut direction)) = query.get_single_mut() {
         // get_single_mut() to get a mutable reference when you know there is ONLY one
-        if kb.pressed(KeyCode::ArrowLeft) {
-            *direction = Direction::Left;
-            velocity.x = -1.;
-        } else if kb.pressed(KeyCode::ArrowRight) {
-            *direction = Direction::Right;
-            velocity.x = 1.;
+        locomotion.max_speed = if actions.pressed(Action::Run) {
+            PLAYER_RUN_SPEED
         } else {
-            velocity.x = 0.;
-        }
+            PLAYER_SPEED
+        };
 
-        if kb.pressed(KeyCode::ArrowDown) {
-            *direction = Direction::Down;
-            velocity.y = -1.;
-        } else if kb.pressed(KeyCode::ArrowUp) {
-            *direction = Direction::Up;
-            velocity.y = 1.;
-        } else {
-            velocity.y = 0.;
+        // The move axis is never longer than 1, so diagonals are no faster than straight
+        // lines. The player keeps looking the same way after stopping.
+        intent.0 = move_axis.0;
+        if intent.0 != Vec2::ZERO {
+            (*facing, *direction) = facing_of(intent.0);
         }
     }
 }
 
-// Check for collisions with tiles
-fn player_tile_collision_system(
-    player_query: Query<(&Transform, &Direction), With<Player>>,
-    wall_query: Query<&Transform, (With<TileCollider>, Without<Player>)>,
+// The nearest of the 8 compass directions to a direction, and the sprite that shows it
+fn facing_of(direction: Vec2) -> (Facing, Direction) {
+    let octant = (direction.y.atan2(direction.x) / FRAC_PI_4).round() as i32;
+    let facing = Facing(Vec2::from_angle(octant as f32 * FRAC_PI_4));
+    let sprite = match octant.rem_euclid(8) {
+        0 => Direction::Right,
+        1..=3 => Direction::Up,
+        4 => Direction::Left,
+        _ => Direction::Down,
+    };
+    (facing, sprite)
+}
+
+// Enemies the player bumps into knock it back away from them
+fn player_knockback_system(
+    mut events: EventReader<CollisionStarted>,
+    mut player_query: Query<(Entity, &Transform, &mut Impulse), With<Player>>,
+    enemy_query: Query<&Transform, With<Enemy>>,
 ) {
-    if let Ok((player_tf, player_dir)) = player_query.get_single() {
-        let collision = collision_check_system(player_tf.translation, &wall_query);
-        if collision {
-            println!("Collision!");
-        }
+    let Ok((player, player_tf, mut impulse)) = player_query.get_single_mut() else {
+        return;
+    };
+    for event in events.read() {
+        let Some(enemy_tf) = event.other(player).and_then(|enemy| enemy_query.get(enemy).ok())
+        else {
+            continue;
+        };
+        let away = (player_tf.translation - enemy_tf.translation).truncate();
+        impulse.0 += away.normalize_or_zero() * PLAYER_KNOCKBACK;
     }
 }
@@ -1,10 +1,13 @@
 use bevy::{
     asset::{Handle, LoadedFolder},
-    prelude::Resource,
+    prelude::{Deref, DerefMut, Resource},
     render::texture::Image,
     sprite::TextureAtlasLayout,
+    time::Timer,
 };
 
+use crate::tilemap::TileMap;
+
 // Resources
 #[derive(Resource)]
 pub struct WinSize {
@@ -21,5 +24,14 @@ pub struct GameTextures {
     pub player_laser: Handle<Image>,
 }
 
+// The map that is currently loaded (or loading)
+#[derive(Resource)]
+pub struct CurrentMap(pub Handle<TileMap>);
+
+// Shared by every animated tile so copies of an animation stay in step, the tile
+// counterpart of the AnimationTimer sprites use
+#[derive(Resource, Deref, DerefMut)]
+pub struct TileAnimationTimer(pub Timer);
+
 #[derive(Resource, Debug)]
 pub struct RpgSpriteFolder(Handle<LoadedFolder>);
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::f32::consts::FRAC_PI_2;
+
+use crate::components::{AnimatedTile, AnimatedTileMesh};
+use crate::resources::TileAnimationTimer;
+use crate::tilemap::{TileLayer, TileMap};
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    render::{
+        mesh::{Indices, PrimitiveTopology},
+        render_asset::RenderAssetUsages,
+    },
+};
+
+// How chunks draw their tiles. Batched builds one mesh per layer and tileset in each chunk,
+// Sprites spawns one sprite entity per tile, which is slower but easy to inspect.
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TileRenderMode {
+    #[default]
+    Batched,
+    Sprites,
+}
+
+// One material per tileset texture, shared by every chunk mesh that uses it
+#[derive(Resource, Default)]
+pub struct TileMaterials(HashMap<AssetId<Image>, Handle<ColorMaterial>>);
+
+// Everything chunk spawning needs to build tile meshes
+#[derive(SystemParam)]
+pub struct TileMeshAssets<'w> {
+    pub render_mode: Res<'w, TileRenderMode>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<ColorMaterial>>,
+    layouts: Res<'w, Assets<TextureAtlasLayout>>,
+    tile_materials: ResMut<'w, TileMaterials>,
+    time: Res<'w, Time>,
+}
+
+impl TileMeshAssets<'_> {
+    pub fn material(&mut self, texture: &Handle<Image>) -> Handle<ColorMaterial> {
+        let materials = &mut self.materials;
+        self.tile_materials
+            .0
+            .entry(texture.id())
+            .or_insert_with(|| materials.add(ColorMaterial::from(texture.clone())))
+            .clone()
+    }
+
+    // Point in time animated tiles are drawn at, the same for every chunk
+    pub fn animation_time(&self) -> f32 {
+        self.time.elapsed_seconds_wrapped()
+    }
+
+    // Build the mesh for the tiles of one layer that use one tileset within the given tiles.
+    // Returns None if none of those tiles use the tileset.
+    pub fn layer_mesh(
+        &mut self,
+        map: &TileMap,
+        layer: &TileLayer,
+        tileset: usize,
+        tiles: impl Iterator<Item = IVec2>,
+    ) -> Option<Handle<Mesh>> {
+        let mesh = self.build_layer_mesh(map, layer, tileset, tiles)?;
+        Some(self.meshes.add(mesh))
+    }
+
+    // Rebuild an existing layer mesh in place, e.g. to show the next frame of animated tiles
+    pub fn update_layer_mesh(
+        &mut self,
+        mesh: &Handle<Mesh>,
+        map: &TileMap,
+        layer: &TileLayer,
+        tileset: usize,
+        tiles: impl Iterator<Item = IVec2>,
+    ) {
+        if let Some(new_mesh) = self.build_layer_mesh(map, layer, tileset, tiles) {
+            self.meshes.insert(mesh, new_mesh);
+        }
+    }
+
+    fn build_layer_mesh(
+        &self,
+        map: &TileMap,
+        layer: &TileLayer,
+        tileset: usize,
+        tiles: impl Iterator<Item = IVec2>,
+    ) -> Option<Mesh> {
+        let layout = self.layouts.get(&map.tilesets[tileset].layout)?;
+        let time = self.animation_time();
+        let half_size = map.grid.tile_size / 2.;
+
+        let mut positions: Vec<[f32; 3]> = Vec::new();
+        let mut uvs: Vec<[f32; 2]> = Vec::new();
+        let mut colors: Vec<[f32; 4]> = Vec::new();
+        let mut indices: Vec<u32> = Vec::new();
+
+        for tile_pos in tiles {
+            let Some(tile) = layer.tiles[map.tile_index(tile_pos)] else {
+                continue;
+            };
+            if tile.tileset != tileset {
+                continue;
+            }
+            let index = map.tilesets[tileset].frame(tile.index, time);
+            let Some(rect) = layout.textures.get(index) else {
+                continue;
+            };
+
+            // Corners go counter-clockwise from the bottom left, image v grows downwards
+            let center = map.tile_to_world(tile_pos);
+            let (mut u0, mut u1) = (rect.min.x / layout.size.x, rect.max.x / layout.size.x);
+            let (mut v0, mut v1) = (rect.max.y / layout.size.y, rect.min.y / layout.size.y);
+            if tile.flip_x {
+                std::mem::swap(&mut u0, &mut u1);
+            }
+            if tile.flip_y {
+                std::mem::swap(&mut v0, &mut v1);
+            }
+            let mut corner_uvs = [[u0, v0], [u1, v0], [u1, v1], [u0, v1]];
+
+            // A counter-clockwise quarter turn moves each corner's uv to the next corner
+            let quarter_turns = (tile.rotation / FRAC_PI_2).round() as i32;
+            corner_uvs.rotate_right(quarter_turns.rem_euclid(4) as usize);
+
+            let start = positions.len() as u32;
+            for (corner, uv) in [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)]
+                .into_iter()
+                .zip(corner_uvs)
+            {
+                let position = center + Vec2::new(corner.0, corner.1) * half_size;
+                positions.push([position.x, position.y, 0.]);
+                uvs.push(uv);
+                colors.push(
+                    tile.color
+                        .with_a(tile.color.a() * layer.opacity)
+                        .as_linear_rgba_f32(),
+                );
+            }
+            indices.extend([start, start + 1, start + 2, start, start + 2, start + 3]);
+        }
+
+        if positions.is_empty() {
+            return None;
+        }
+
+        let mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::RENDER_WORLD,
+        )
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_COLOR, colors)
+        .with_inserted_indices(Indices::U32(indices));
+        Some(mesh)
+    }
+}
+
+// F3 switches between batched meshes and per-tile sprites for debugging
+pub fn toggle_render_mode_system(
+    kb: Res<ButtonInput<KeyCode>>,
+    mut render_mode: ResMut<TileRenderMode>,
+) {
+    if kb.just_pressed(KeyCode::F3) {
+        *render_mode = match *render_mode {
+            TileRenderMode::Batched => TileRenderMode::Sprites,
+            TileRenderMode::Sprites => TileRenderMode::Batched,
+        };
+        info!("Tile render mode: {:?}", *render_mode);
+    }
+}
+
+// Show the current frame of every animated tile. Like AnimationTimer does for sprites, frames
+// only advance when the shared timer finishes, and only meshes whose tileset changed frame
+// are rebuilt.
+pub fn tile_animation_system(
+    time: Res<Time>,
+    mut timer: ResMut<TileAnimationTimer>,
+    mut previous_time: Local<f32>,
+    map: Option<Res<TileMap>>,
+    mut mesh_assets: TileMeshAssets,
+    mut sprite_query: Query<(&mut TextureAtlas, &AnimatedTile)>,
+    mesh_query: Query<(&Handle<Mesh>, &AnimatedTileMesh)>,
+) {
+    let Some(map) = map else {
+        return;
+    };
+    timer.tick(time.delta());
+    if !timer.just_finished() {
+        return;
+    }
+
+    let now = mesh_assets.animation_time();
+    let changed: Vec<bool> = map
+        .tilesets
+        .iter()
+        .map(|tileset| {
+            tileset
+                .animations
+                .values()
+                .any(|animation| animation.frame_at(*previous_time) != animation.frame_at(now))
+        })
+        .collect();
+    *previous_time = now;
+
+    for (mut atlas, tile) in sprite_query.iter_mut() {
+        if changed.get(tile.tileset) == Some(&true) {
+            atlas.index = map.tilesets[tile.tileset].frame(tile.index, now);
+        }
+    }
+    for (mesh, animated) in mesh_query.iter() {
+        let Some(layer) = map.layers.get(animated.layer) else {
+            continue;
+        };
+        if changed.get(animated.tileset) == Some(&true) {
+            mesh_assets.update_layer_mesh(
+                mesh,
+                &map,
+                layer,
+                animated.tileset,
+                map.chunk_tiles(animated.chunk),
+            );
+        }
+    }
+}
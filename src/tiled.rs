@@ -0,0 +1,803 @@
+use std::collections::HashMap;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::BoxedFuture,
+};
+use serde::{Deserialize, Deserializer};
+
+use crate::autotile::{AutotileRule, Terrain, EAST, NORTH, SOUTH, WEST};
+use crate::components::Collider;
+use crate::tilemap::{
+    MapObject, PropertyValue, Tile, TileAnimation, TileFrame, TileLayer, TileMap,
+    TileMapLoaderError, Tileset, TilesetDescription,
+};
+
+// Tiled stores tile flips in the high bits of each GID
+pub const FLIPPED_HORIZONTALLY_FLAG: u32 = 0x8000_0000;
+pub const FLIPPED_VERTICALLY_FLAG: u32 = 0x4000_0000;
+pub const FLIPPED_DIAGONALLY_FLAG: u32 = 0x2000_0000;
+const GID_MASK: u32 =
+    !(FLIPPED_HORIZONTALLY_FLAG | FLIPPED_VERTICALLY_FLAG | FLIPPED_DIAGONALLY_FLAG);
+
+// Tileset designers paint solidity with, independent of the visible art
+pub const COLLISION_TILESET: &str = "collisions";
+
+// Subset of the Tiled JSON map format that we actually use
+#[derive(Deserialize, Debug)]
+pub struct TiledMap {
+    pub width: u32,
+    pub height: u32,
+    pub tilewidth: u32,
+    pub tileheight: u32,
+    pub layers: Vec<TiledLayer>,
+    pub tilesets: Vec<TiledTileset>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+pub enum TiledLayer {
+    #[serde(rename = "tilelayer")]
+    TileLayer(TiledTileLayer),
+    #[serde(rename = "objectgroup")]
+    ObjectGroup(TiledObjectGroup),
+    #[serde(other)]
+    Other, // Image and group layers are skipped for now
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TiledTileLayer {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u32>,
+    #[serde(default = "default_true")]
+    pub visible: bool,
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+    #[serde(default, deserialize_with = "deserialize_properties")]
+    pub properties: HashMap<String, PropertyValue>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TiledObjectGroup {
+    #[serde(default)] // Collision shapes of tiles are in unnamed groups
+    pub name: String,
+    pub objects: Vec<TiledObject>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TiledObject {
+    pub id: u32,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default, rename = "type", alias = "class")] // Tiled 1.9 renamed "type" to "class"
+    pub kind: String,
+    pub x: f32,
+    pub y: f32,
+    #[serde(default)]
+    pub width: f32,
+    #[serde(default)]
+    pub height: f32,
+    pub gid: Option<u32>,
+    #[serde(default)]
+    pub ellipse: bool,
+    #[serde(default, deserialize_with = "deserialize_properties")]
+    pub properties: HashMap<String, PropertyValue>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TiledTileset {
+    pub firstgid: u32,
+    pub name: String,
+    pub image: String, // Relative to the map file
+    pub tilewidth: u32,
+    pub tileheight: u32,
+    pub columns: u32,
+    pub tilecount: u32,
+    #[serde(default)]
+    pub margin: u32,
+    #[serde(default)]
+    pub spacing: u32,
+    #[serde(default, deserialize_with = "deserialize_tiles")]
+    pub tiles: Vec<TiledTileData>,
+    #[serde(default)]
+    pub wangsets: Vec<TiledWangSet>,
+}
+
+// Extra data of a single tile in a tileset, we only use its animation, collision shape and
+// a "traction" property
+#[derive(Deserialize, Debug)]
+pub struct TiledTileData {
+    #[serde(default)]
+    pub id: u32, // Older Tiled versions key the tiles by id instead
+    #[serde(default)]
+    pub animation: Vec<TiledFrame>,
+    #[serde(default, deserialize_with = "deserialize_properties")]
+    pub properties: HashMap<String, PropertyValue>,
+    pub objectgroup: Option<TiledObjectGroup>, // Drawn in Tiled's tile collision editor
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TiledFrame {
+    pub tileid: u32,
+    pub duration: u32, // Milliseconds
+}
+
+// Tiled's terrain painting rules, colors named after a Terrain become autotile rules
+#[derive(Deserialize, Debug)]
+pub struct TiledWangSet {
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub kind: String, // "edge", "corner" or "mixed", only edge sets match our autotile rules
+    #[serde(default)]
+    pub colors: Vec<TiledWangColor>,
+    #[serde(default)]
+    pub wangtiles: Vec<TiledWangTile>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TiledWangColor {
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TiledWangTile {
+    pub tileid: u32,
+    pub wangid: [u8; 8], // Colors clockwise from the top edge, alternating edges and corners
+}
+
+// Older Tiled versions write properties as a map, newer ones as a list of {name, type, value}
+fn deserialize_properties<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<String, PropertyValue>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct TiledProperty {
+        name: String,
+        value: PropertyValue,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum TiledProperties {
+        Map(HashMap<String, PropertyValue>),
+        List(Vec<TiledProperty>),
+    }
+
+    Ok(match TiledProperties::deserialize(deserializer)? {
+        TiledProperties::Map(map) => map,
+        TiledProperties::List(list) => list
+            .into_iter()
+            .map(|property| (property.name, property.value))
+            .collect(),
+    })
+}
+
+// Tiles are a list with ids since Tiled 1.2, older versions wrote an object keyed by tile id
+fn deserialize_tiles<'de, D>(deserializer: D) -> Result<Vec<TiledTileData>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum TiledTiles {
+        Map(HashMap<String, TiledTileData>),
+        List(Vec<TiledTileData>),
+    }
+
+    match TiledTiles::deserialize(deserializer)? {
+        TiledTiles::Map(map) => map
+            .into_iter()
+            .map(|(id, tile)| {
+                let id = id
+                    .parse()
+                    .map_err(|_| serde::de::Error::custom(format!("invalid tile id '{}'", id)))?;
+                Ok(TiledTileData { id, ..tile })
+            })
+            .collect(),
+        TiledTiles::List(list) => Ok(list),
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_opacity() -> f32 {
+    1.
+}
+
+// A decoded GID: the tile id without the flip bits, plus the flips themselves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gid {
+    pub id: u32,
+    pub flip_h: bool,
+    pub flip_v: bool,
+    pub flip_d: bool,
+}
+
+impl Gid {
+    pub fn from_raw(raw: u32) -> Self {
+        Gid {
+            id: raw & GID_MASK,
+            flip_h: raw & FLIPPED_HORIZONTALLY_FLAG != 0,
+            flip_v: raw & FLIPPED_VERTICALLY_FLAG != 0,
+            flip_d: raw & FLIPPED_DIAGONALLY_FLAG != 0,
+        }
+    }
+
+    // GID 0 means there is no tile in this cell
+    pub fn is_empty(&self) -> bool {
+        self.id == 0
+    }
+
+    // Sprite flips and z rotation (radians) that reproduce the Tiled flips.
+    // Tiled applies the diagonal flip first, then horizontal, then vertical. A diagonal
+    // flip is the same as flipping x and rotating 90 degrees counter-clockwise.
+    pub fn sprite_flips(&self) -> (bool, bool, f32) {
+        if self.flip_d {
+            (!self.flip_v, self.flip_h, std::f32::consts::FRAC_PI_2)
+        } else {
+            (self.flip_h, self.flip_v, 0.)
+        }
+    }
+}
+
+impl TiledTileLayer {
+    // A layer is treated as collision data if it is named like one ("Collisions Layer")
+    // or if its tiles come from the "collisions" tileset
+    pub fn is_collision_layer(&self, tilesets: &[TilesetDescription]) -> bool {
+        if self.name.to_lowercase().contains("collision") {
+            return true;
+        }
+
+        self.data
+            .iter()
+            .map(|raw| Gid::from_raw(*raw))
+            .find(|gid| !gid.is_empty())
+            .and_then(|gid| TilesetDescription::find(tilesets, gid.id))
+            .is_some_and(|(tileset, _)| tilesets[tileset].name == COLLISION_TILESET)
+    }
+
+    // Layers with an "autotile" property set have their terrain tiles replaced by the
+    // variant fitting their neighbours when the map loads, other layers are kept as drawn
+    pub fn is_autotiled(&self) -> bool {
+        self.properties.get("autotile") == Some(&PropertyValue::Bool(true))
+    }
+}
+
+impl TiledObject {
+    // Center of the object in map pixels (y down). Tile objects are anchored bottom-left,
+    // every other object top-left.
+    pub fn center(&self) -> Vec2 {
+        let top = if self.gid.is_some() {
+            self.y - self.height
+        } else {
+            self.y
+        };
+        Vec2::new(self.x + self.width / 2., top + self.height / 2.)
+    }
+}
+
+impl TiledTileset {
+    pub fn rows(&self) -> u32 {
+        self.tilecount.div_ceil(self.columns.max(1))
+    }
+
+    // Grid of the tileset image, Tiled calls the padding spacing and the offset margin
+    pub fn description(&self, image: String) -> TilesetDescription {
+        TilesetDescription {
+            name: self.name.clone(),
+            image,
+            tile_size: Vec2::new(self.tilewidth as f32, self.tileheight as f32),
+            columns: self.columns as usize,
+            rows: self.rows() as usize,
+            padding: Vec2::splat(self.spacing as f32),
+            offset: Vec2::splat(self.margin as f32),
+            first_gid: self.firstgid,
+        }
+    }
+
+    // Tile animations by the local id of the animated tile
+    pub fn animations(&self) -> HashMap<usize, TileAnimation> {
+        self.tiles
+            .iter()
+            .filter(|tile| !tile.animation.is_empty())
+            .map(|tile| {
+                let frames = tile
+                    .animation
+                    .iter()
+                    .map(|frame| TileFrame {
+                        index: frame.tileid as usize,
+                        duration: frame.duration as f32 / 1000.,
+                    })
+                    .collect();
+                (tile.id as usize, TileAnimation { frames })
+            })
+            .collect()
+    }
+
+    // Grip of the tiles with a "traction" property, by their local id
+    pub fn traction(&self) -> HashMap<usize, f32> {
+        self.tiles
+            .iter()
+            .filter_map(|tile| match tile.properties.get("traction")? {
+                PropertyValue::Float(value) => Some((tile.id as usize, *value as f32)),
+                PropertyValue::Int(value) => Some((tile.id as usize, *value as f32)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Collision shapes by the local id of their tile, in pixels from the center of the tile
+    // with y pointing down. Only the first rectangle or ellipse of each tile is used.
+    pub fn colliders(&self) -> HashMap<usize, Collider> {
+        let center = Vec2::new(self.tilewidth as f32, self.tileheight as f32) / 2.;
+        self.tiles
+            .iter()
+            .filter_map(|tile| {
+                let object = tile
+                    .objectgroup
+                    .as_ref()?
+                    .objects
+                    .iter()
+                    .find(|object| object.width > 0. && object.height > 0.)?;
+                let size = Vec2::new(object.width, object.height);
+                let collider = if object.ellipse {
+                    Collider::circle(size.min_element() / 2.)
+                } else {
+                    Collider::aabb(size / 2.)
+                };
+                let offset = Vec2::new(object.x, object.y) + size / 2. - center;
+                Some((tile.id as usize, collider.with_offset(offset)))
+            })
+            .collect()
+    }
+
+    // Autotile rules from the wang sets, the first tile found for each set of sides is used
+    pub fn autotiles(&self) -> HashMap<Terrain, AutotileRule> {
+        let mut autotiles: HashMap<Terrain, AutotileRule> = HashMap::new();
+        for wangset in self.wangsets.iter() {
+            if wangset.kind != "edge" {
+                warn!(
+                    "Wang set '{}' is a {} set, only edge sets are used for autotiling",
+                    wangset.name, wangset.kind
+                );
+                continue;
+            }
+            for (i, color) in wangset.colors.iter().enumerate() {
+                let Some(terrain) = Terrain::from_name(&color.name) else {
+                    warn!(
+                        "Wang color '{}' in '{}' is not a known terrain",
+                        color.name, wangset.name
+                    );
+                    continue;
+                };
+                // Tiles that also have edges of another color belong to a transition
+                // between terrains, which our rules can't describe
+                let color = i as u8 + 1;
+                let rule = autotiles.entry(terrain).or_default();
+                for tile in wangset.wangtiles.iter().filter(|tile| tile.only_has(color)) {
+                    rule.variants[tile.connected_sides(color)] = Some(tile.tileid as usize);
+                }
+            }
+        }
+        autotiles
+    }
+}
+
+impl TiledWangTile {
+    // Bitmask of the edges that have the given color
+    pub fn connected_sides(&self, color: u8) -> usize {
+        [NORTH, EAST, SOUTH, WEST]
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| self.wangid[i * 2] == color)
+            .fold(0, |mask, (_, side)| mask | side)
+    }
+
+    // Whether every edge is either the given color or empty
+    pub fn only_has(&self, color: u8) -> bool {
+        self.wangid
+            .iter()
+            .step_by(2)
+            .all(|edge| *edge == 0 || *edge == color)
+    }
+}
+
+// Loads Tiled JSON maps (.tmj, or .json as exported by older Tiled versions)
+#[derive(Default)]
+pub struct TiledMapLoader;
+
+impl AssetLoader for TiledMapLoader {
+    type Asset = TileMap;
+    type Settings = ();
+    type Error = TileMapLoaderError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<TileMap, TileMapLoaderError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            let tiled: TiledMap = serde_json::from_slice(&bytes)?;
+            tiled.into_tile_map(load_context)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tmj", "json"]
+    }
+}
+
+impl TiledMap {
+    fn into_tile_map(self, load_context: &mut LoadContext) -> Result<TileMap, TileMapLoaderError> {
+        let mut map = TileMap::new(
+            self.width,
+            self.height,
+            Vec2::new(self.tilewidth as f32, self.tileheight as f32),
+        );
+
+        // Tileset images are relative to the map file, each gets its own atlas layout
+        let mut descriptions = Vec::new();
+        for tileset in self.tilesets.iter() {
+            let image_path = load_context
+                .asset_path()
+                .resolve_embed(&tileset.image)
+                .map_err(|err| TileMapLoaderError::Invalid(err.to_string()))?;
+            descriptions.push(tileset.description(image_path.to_string()));
+        }
+        TilesetDescription::check_overlap(&descriptions)?;
+        for (i, (tileset, description)) in self.tilesets.iter().zip(&descriptions).enumerate() {
+            map.tilesets.push(Tileset {
+                animations: tileset.animations(),
+                autotiles: tileset.autotiles(),
+                traction: tileset.traction(),
+                ..description.load(load_context, format!("tileset{}", i))
+            });
+        }
+
+        let mut autotiled = Vec::new();
+        let colliders: Vec<HashMap<usize, Collider>> =
+            self.tilesets.iter().map(TiledTileset::colliders).collect();
+        for layer in self.layers.iter() {
+            match layer {
+                TiledLayer::TileLayer(layer) => {
+                    if layer.width != self.width || layer.height != self.height {
+                        return Err(TileMapLoaderError::Invalid(format!(
+                            "layer '{}' is {}x{} but the map is {}x{}",
+                            layer.name, layer.width, layer.height, self.width, self.height
+                        )));
+                    }
+
+                    // Tiles with a collision shape make their cell solid in that shape, whatever
+                    // layer they are in. Flipped tiles keep the shape as drawn.
+                    for (i, raw) in layer.data.iter().enumerate() {
+                        let gid = Gid::from_raw(*raw);
+                        let collider = TilesetDescription::find(&descriptions, gid.id)
+                            .filter(|_| !gid.is_empty())
+                            .and_then(|(tileset, index)| colliders[tileset].get(&index));
+                        if let Some(collider) = collider {
+                            map.solid[i] = true;
+                            map.partial_colliders
+                                .insert(map.index_to_tile(i), *collider);
+                        }
+                    }
+
+                    // Collision layers only decide solidity, their art is never drawn
+                    if layer.is_collision_layer(&descriptions) {
+                        for (solid, raw) in map.solid.iter_mut().zip(layer.data.iter()) {
+                            *solid |= !Gid::from_raw(*raw).is_empty();
+                        }
+                        continue;
+                    }
+
+                    // Tiles that are variants of an autotile rule get that rule's terrain, tiles
+                    // with a collision shape keep it so replacing them updates the cell
+                    let tiles = layer
+                        .data
+                        .iter()
+                        .map(|raw| {
+                            tile(&descriptions, Gid::from_raw(*raw)).map(|tile| Tile {
+                                terrain: map.tilesets[tile.tileset].terrain_of(tile.index),
+                                collider: colliders[tile.tileset].get(&tile.index).copied(),
+                                ..tile
+                            })
+                        })
+                        .collect();
+                    if layer.is_autotiled() {
+                        autotiled.push(map.layers.len());
+                    }
+                    map.layers.push(TileLayer {
+                        name: layer.name.clone(),
+                        tiles,
+                        visible: layer.visible,
+                        opacity: layer.opacity,
+                    });
+                }
+                TiledLayer::ObjectGroup(group) => {
+                    map.objects
+                        .extend(group.objects.iter().map(|object| MapObject {
+                            name: object.name.clone(),
+                            kind: object.kind.clone(),
+                            position: object.center(),
+                            size: Vec2::new(object.width, object.height),
+                            properties: object.properties.clone(),
+                        }));
+                }
+                TiledLayer::Other => {}
+            }
+        }
+
+        for layer in autotiled {
+            map.autotile_layer(layer);
+        }
+        Ok(map)
+    }
+}
+
+fn tile(tilesets: &[TilesetDescription], gid: Gid) -> Option<Tile> {
+    if gid.is_empty() {
+        return None;
+    }
+    let Some((tileset, index)) = TilesetDescription::find(tilesets, gid.id) else {
+        warn!("GID {} has no tileset", gid.id);
+        return None;
+    };
+    let (flip_x, flip_y, rotation) = gid.sprite_flips();
+    Some(Tile {
+        flip_x,
+        flip_y,
+        rotation,
+        ..Tile::new(tileset, index)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use bevy::math::Vec2;
+
+    use super::*;
+
+    fn gid(id: u32, flip_h: bool, flip_v: bool, flip_d: bool) -> Gid {
+        Gid {
+            id,
+            flip_h,
+            flip_v,
+            flip_d,
+        }
+    }
+
+    #[test]
+    fn raw_gids_split_into_a_tile_id_and_flips() {
+        let cases = [
+            (0, gid(0, false, false, false)),
+            (57, gid(57, false, false, false)),
+            (57 | FLIPPED_HORIZONTALLY_FLAG, gid(57, true, false, false)),
+            (57 | FLIPPED_VERTICALLY_FLAG, gid(57, false, true, false)),
+            (57 | FLIPPED_DIAGONALLY_FLAG, gid(57, false, false, true)),
+            (0xE000_0039, gid(57, true, true, true)),
+            (FLIPPED_HORIZONTALLY_FLAG, gid(0, true, false, false)),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(Gid::from_raw(raw), expected, "{:#x}", raw);
+        }
+        assert!(Gid::from_raw(FLIPPED_VERTICALLY_FLAG).is_empty());
+    }
+
+    #[test]
+    fn flips_become_sprite_flips_and_a_quarter_turn() {
+        let cases = [
+            (gid(1, false, false, false), (false, false, 0.)),
+            (gid(1, true, false, false), (true, false, 0.)),
+            (gid(1, false, true, false), (false, true, 0.)),
+            (gid(1, true, true, false), (true, true, 0.)),
+            (gid(1, false, false, true), (true, false, FRAC_PI_2)),
+            (gid(1, true, false, true), (true, true, FRAC_PI_2)),
+            (gid(1, false, true, true), (false, false, FRAC_PI_2)),
+            (gid(1, true, true, true), (false, true, FRAC_PI_2)),
+        ];
+        for (gid, expected) in cases {
+            assert_eq!(gid.sprite_flips(), expected, "{:?}", gid);
+        }
+    }
+
+    #[test]
+    fn sprite_flips_move_corners_where_tiled_does() {
+        // Tiled flips in screen space (y down): diagonal swaps x and y, then horizontal
+        // and vertical negate them. Bevy flips the sprite first, then rotates it (y up).
+        let corner = Vec2::new(1., 2.);
+        for raw in 0..8u32 {
+            let gid = Gid::from_raw(raw << 29);
+            let mut tiled = Vec2::new(corner.x, -corner.y);
+            if gid.flip_d {
+                tiled = Vec2::new(tiled.y, tiled.x);
+            }
+            if gid.flip_h {
+                tiled.x = -tiled.x;
+            }
+            if gid.flip_v {
+                tiled.y = -tiled.y;
+            }
+
+            let (flip_x, flip_y, rotation) = gid.sprite_flips();
+            let flipped = Vec2::new(
+                if flip_x { -corner.x } else { corner.x },
+                if flip_y { -corner.y } else { corner.y },
+            );
+            let sprite = Vec2::from_angle(rotation).rotate(flipped);
+            assert!(
+                (sprite - Vec2::new(tiled.x, -tiled.y)).length() < 1e-5,
+                "{:?}",
+                gid
+            );
+        }
+    }
+
+    #[test]
+    fn collision_layers_are_found_by_name_or_by_tileset() {
+        let map: TiledMap = serde_json::from_str(
+            r#"{
+                "width": 2, "height": 1, "tilewidth": 16, "tileheight": 16,
+                "layers": [
+                    {"type": "tilelayer", "name": "Collisions Layer", "width": 2, "height": 1, "data": [0, 0]},
+                    {"type": "tilelayer", "name": "Walls", "width": 2, "height": 1, "data": [0, 2147483653]},
+                    {"type": "tilelayer", "name": "Ground", "width": 2, "height": 1, "data": [1, 5]},
+                    {"type": "tilelayer", "name": "Empty", "width": 2, "height": 1, "data": [0, 0]}
+                ],
+                "tilesets": [
+                    {"firstgid": 1, "name": "ground", "image": "ground.png", "tilewidth": 16,
+                     "tileheight": 16, "columns": 2, "tilecount": 4},
+                    {"firstgid": 5, "name": "collisions", "image": "collisions.png", "tilewidth": 16,
+                     "tileheight": 16, "columns": 1, "tilecount": 1}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let tilesets: Vec<TilesetDescription> = map
+            .tilesets
+            .iter()
+            .map(|tileset| tileset.description(tileset.image.clone()))
+            .collect();
+
+        // The first tile of a layer decides its tileset, flipped or not
+        let found: Vec<(&str, bool)> = map
+            .layers
+            .iter()
+            .filter_map(|layer| match layer {
+                TiledLayer::TileLayer(layer) => Some(layer),
+                _ => None,
+            })
+            .map(|layer| (layer.name.as_str(), layer.is_collision_layer(&tilesets)))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("Collisions Layer", true),
+                ("Walls", true),
+                ("Ground", false),
+                ("Empty", false)
+            ]
+        );
+    }
+
+    #[test]
+    fn tiles_are_read_as_a_list_or_keyed_by_id() {
+        let tileset = |tiles: &str| {
+            serde_json::from_str::<TiledTileset>(&format!(
+                r#"{{"firstgid": 1, "name": "water", "image": "water.png", "tilewidth": 16,
+                    "tileheight": 16, "columns": 4, "tilecount": 16, "tiles": {}}}"#,
+                tiles
+            ))
+        };
+        let expected = HashMap::from([(
+            3,
+            TileAnimation {
+                frames: vec![
+                    TileFrame {
+                        index: 3,
+                        duration: 0.25,
+                    },
+                    TileFrame {
+                        index: 4,
+                        duration: 0.25,
+                    },
+                ],
+            },
+        )]);
+
+        // Tiled 1.2 and later
+        let list = tileset(
+            r#"[{"id": 3, "animation": [{"tileid": 3, "duration": 250}, {"tileid": 4, "duration": 250}]}]"#,
+        )
+        .unwrap();
+        assert_eq!(list.animations(), expected);
+
+        // Tiled 1.0, as in map.json
+        let map = tileset(
+            r#"{"3": {"animation": [{"tileid": 3, "duration": 250}, {"tileid": 4, "duration": 250}]}}"#,
+        )
+        .unwrap();
+        assert_eq!(map.animations(), expected);
+
+        assert!(tileset(r#"{"water": {}}"#).is_err());
+    }
+
+    #[test]
+    fn wang_ids_become_masks_of_the_sides_with_a_color() {
+        let cases = [
+            ([0, 0, 0, 0, 0, 0, 0, 0], 0),
+            ([1, 0, 0, 0, 0, 0, 0, 0], NORTH),
+            ([1, 0, 1, 0, 1, 0, 1, 0], NORTH | EAST | SOUTH | WEST),
+            ([2, 0, 1, 0, 2, 0, 1, 0], EAST | WEST),
+            ([2, 2, 2, 2, 2, 2, 2, 2], 0),
+            // Corners never connect
+            ([0, 1, 0, 1, 0, 1, 0, 1], 0),
+            ([1, 1, 0, 1, 0, 0, 0, 1], NORTH),
+        ];
+        for (wangid, mask) in cases {
+            let tile = TiledWangTile { tileid: 0, wangid };
+            assert_eq!(tile.connected_sides(1), mask, "{:?}", wangid);
+        }
+    }
+
+    #[test]
+    fn only_edge_wang_sets_become_autotile_rules() {
+        let tileset: TiledTileset = serde_json::from_str(
+            r#"{
+                "firstgid": 1, "name": "terrain", "image": "terrain.png", "tilewidth": 16,
+                "tileheight": 16, "columns": 4, "tilecount": 16,
+                "wangsets": [
+                    {"name": "edges", "type": "edge", "colors": [{"name": "Wall"}, {"name": "Water"}],
+                     "wangtiles": [
+                        {"tileid": 0, "wangid": [0, 0, 0, 0, 0, 0, 0, 0]},
+                        {"tileid": 1, "wangid": [1, 0, 0, 0, 1, 0, 0, 0]},
+                        {"tileid": 2, "wangid": [1, 0, 2, 0, 1, 0, 0, 0]},
+                        {"tileid": 3, "wangid": [0, 0, 2, 0, 0, 0, 2, 0]}
+                     ]},
+                    {"name": "corners", "type": "corner", "colors": [{"name": "Cliff"}],
+                     "wangtiles": [{"tileid": 4, "wangid": [0, 1, 0, 1, 0, 1, 0, 1]}]}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let autotiles = tileset.autotiles();
+
+        // Tile 2 joins walls and water, so it is in neither rule
+        let wall = &autotiles[&Terrain::Wall];
+        assert_eq!(wall.variants[0], Some(0));
+        assert_eq!(wall.variants[NORTH | SOUTH], Some(1));
+        assert!(!wall.contains(2));
+        let water = &autotiles[&Terrain::Water];
+        assert_eq!(water.variants[EAST | WEST], Some(3));
+        assert!(!water.contains(2));
+        assert!(!autotiles.contains_key(&Terrain::Cliff));
+    }
+
+    #[test]
+    fn layers_are_autotiled_when_they_ask_for_it() {
+        let layer = |properties: &str| {
+            serde_json::from_str::<TiledTileLayer>(&format!(
+                r#"{{"name": "Walls", "width": 1, "height": 1, "data": [0], "properties": {}}}"#,
+                properties
+            ))
+            .unwrap()
+            .is_autotiled()
+        };
+        assert!(layer(
+            r#"[{"name": "autotile", "type": "bool", "value": true}]"#
+        ));
+        assert!(layer(r#"{"autotile": true}"#));
+        assert!(!layer(r#"{"autotile": false}"#));
+        assert!(!layer("[]"));
+    }
+}
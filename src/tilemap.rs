@@ -1,55 +1,704 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::collections::{HashMap, HashSet};
 
-use crate::ascii::{spawn_ascii_sprite, AsciiSheet};
-use crate::components::TileCollider;
-use crate::{AppState, WinSize, TILE_SIZE};
-use bevy::{prelude::*, transform::commands};
+use crate::ascii::AsciiMapLoader;
+use crate::autotile::{AutotileRule, Terrain};
+use crate::chunks::{
+    chunk_streaming_system, refresh_changed_chunks_system, spawn_tile_collider, LoadedChunks,
+    MapColliders,
+};
+use crate::components::{Collider, Layer, LevelExit, MapRoot, Sensor};
+use crate::constants::{CHUNK_SIZE, TILE_ANIMATION_STEP};
+use crate::dungeon::DungeonLoader;
+use crate::objects::{MapObjectAppExt, ObjectRegistry};
+use crate::resources::{CurrentMap, TileAnimationTimer};
+use crate::tile_mesh::{
+    tile_animation_system, toggle_render_mode_system, TileMaterials, TileRenderMode,
+};
+use crate::tiled::TiledMapLoader;
+use crate::{AppState, START_MAP};
+use bevy::{
+    asset::LoadContext,
+    ecs::system::EntityCommands,
+    prelude::*,
+    render::texture::{ImageLoaderSettings, ImageSampler},
+    transform::TransformSystem,
+};
+use serde::Deserialize;
+use thiserror::Error;
 
 pub struct TileMapPlugin;
 
 impl Plugin for TileMapPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(AppState::Setup), spawn_ascii_map);
+        app.init_asset::<TileMap>()
+            .init_resource::<TileRenderMode>()
+            .init_resource::<TileMaterials>()
+            .add_event::<TileChanged>()
+            .insert_resource(TileAnimationTimer(Timer::from_seconds(
+                TILE_ANIMATION_STEP,
+                TimerMode::Repeating,
+            )))
+            .register_asset_loader(AsciiMapLoader)
+            .register_asset_loader(TiledMapLoader)
+            .register_asset_loader(DungeonLoader)
+            .register_map_object("exit", spawn_exit)
+            .add_systems(OnEnter(AppState::Preload), load_map)
+            .add_systems(OnEnter(AppState::Setup), spawn_map_system)
+            .add_systems(
+                Update,
+                (
+                    reload_map_system,
+                    toggle_render_mode_system,
+                    tile_animation_system,
+                )
+                    .run_if(in_state(AppState::InGame)),
+            )
+            .add_systems(
+                PostUpdate,
+                // Runs after this frame's map changes are applied, before transforms propagate
+                (refresh_changed_chunks_system, chunk_streaming_system)
+                    .chain()
+                    .before(TransformSystem::TransformPropagate)
+                    .run_if(in_state(AppState::InGame)),
+            );
     }
 }
 
-fn spawn_ascii_map(
+// A map loaded from any supported file format, ready to be spawned. Once spawned, a copy
+// of it is also the TileMap resource describing the level that is currently in the world.
+#[derive(Asset, TypePath, Resource, Debug, Clone)]
+pub struct TileMap {
+    pub width: u32,
+    pub height: u32,
+    pub grid: MapGrid,
+    pub tilesets: Vec<Tileset>,
+    pub layers: Vec<TileLayer>, // Drawn in order, later layers on top
+    pub solid: Vec<bool>,       // One entry per cell, row-major with row 0 at the top
+    pub partial_colliders: HashMap<IVec2, Collider>, // Solid cells not filled completely
+    pub objects: Vec<MapObject>,
+    changed_tiles: HashSet<IVec2>, // Changed at runtime, their chunks still need respawning
+}
+
+// Sent once tiles changed at runtime have had their art and colliders respawned. Solidity
+// is already up to date in the TileMap resource by then.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct TileChanged {
+    pub tile: IVec2,
+}
+
+// Which way rows grow in the world. Map files list their rows top to bottom, so Down shows
+// them as written and Up shows them vertically flipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowDirection {
+    Down,
+    Up,
+}
+
+// Grid to world transform of a map
+#[derive(Debug, Clone, Copy)]
+pub struct MapGrid {
+    pub origin: Vec2, // World position of the outer corner of tile (0, 0)
+    pub tile_size: Vec2,
+    pub rows: RowDirection,
+}
+
+#[derive(Debug, Clone)]
+pub struct Tileset {
+    pub name: String,
+    pub texture: Handle<Image>,
+    pub layout: Handle<TextureAtlasLayout>,
+    pub animations: HashMap<usize, TileAnimation>, // By the tile index placed in the map
+    pub autotiles: HashMap<Terrain, AutotileRule>,
+    pub traction: HashMap<usize, f32>, // Grip of tiles that aren't plain ground, by tile index
+}
+
+// Where the tiles of a tileset are in its image, tiles are numbered left to right and top
+// to bottom. Map files number tiles across all their tilesets, so each one has a first GID.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TilesetDescription {
+    pub name: String,
+    pub image: String,   // Asset path of the image
+    pub tile_size: Vec2, // Size of one tile in the image, in pixels
+    pub columns: usize,
+    pub rows: usize,
+    pub padding: Vec2, // Gap between neighbouring tiles
+    pub offset: Vec2,  // Position of the first tile in the image
+    pub first_gid: u32,
+}
+
+// Frames an animated tile cycles through. Every tile using the animation shows the same frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileAnimation {
+    pub frames: Vec<TileFrame>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TileFrame {
+    pub index: usize,  // Tile index in the same tileset
+    pub duration: f32, // Seconds
+}
+
+#[derive(Debug, Clone)]
+pub struct TileLayer {
+    pub name: String,
+    pub tiles: Vec<Option<Tile>>, // Row-major with row 0 at the top
+    pub visible: bool,
+    pub opacity: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub tileset: usize,
+    pub index: usize,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub rotation: f32, // Radians around z, applied after the flips
+    pub color: Color,
+    pub terrain: Option<Terrain>, // Terrain tiles are swapped for the variant that fits their neighbours
+    pub collider: Option<Collider>, // Makes the cell solid, stored like partial colliders
+}
+
+#[derive(Debug, Clone)]
+pub struct MapObject {
+    pub name: String,
+    pub kind: String,
+    pub position: Vec2, // Center of the object in map pixels, y pointing down
+    pub size: Vec2,
+    pub properties: HashMap<String, PropertyValue>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum PropertyValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+#[derive(Debug, Error)]
+pub enum TileMapLoaderError {
+    #[error("could not read map file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse map JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid map: {0}")]
+    Invalid(String),
+}
+
+impl MapGrid {
+    // A grid with rows going down, centered on the world origin
+    pub fn centered(width: u32, height: u32, tile_size: Vec2) -> Self {
+        let size = Vec2::new(width as f32, height as f32) * tile_size;
+        MapGrid {
+            origin: Vec2::new(-size.x / 2., size.y / 2.),
+            tile_size,
+            rows: RowDirection::Down,
+        }
+    }
+
+    // Convert fractional grid coordinates (in tiles, from the corner of tile (0, 0)) to world space
+    pub fn grid_to_world(&self, grid: Vec2) -> Vec2 {
+        let offset = grid * self.tile_size;
+        match self.rows {
+            RowDirection::Down => self.origin + Vec2::new(offset.x, -offset.y),
+            RowDirection::Up => self.origin + offset,
+        }
+    }
+
+    pub fn world_to_grid(&self, world: Vec2) -> Vec2 {
+        let offset = world - self.origin;
+        let offset = match self.rows {
+            RowDirection::Down => Vec2::new(offset.x, -offset.y),
+            RowDirection::Up => offset,
+        };
+        offset / self.tile_size
+    }
+
+    // World position of the center of a tile
+    pub fn tile_to_world(&self, tile: IVec2) -> Vec2 {
+        self.grid_to_world(tile.as_vec2() + 0.5)
+    }
+
+    // Tile containing a world position, which may lie outside the map
+    pub fn world_to_tile(&self, world: Vec2) -> IVec2 {
+        self.world_to_grid(world).floor().as_ivec2()
+    }
+}
+
+impl TileMap {
+    // An empty map centered on the origin, every loader fills in its layers, solidity and objects
+    pub fn new(width: u32, height: u32, tile_size: Vec2) -> Self {
+        TileMap {
+            width,
+            height,
+            grid: MapGrid::centered(width, height, tile_size),
+            tilesets: Vec::new(),
+            layers: Vec::new(),
+            solid: vec![false; (width * height) as usize],
+            partial_colliders: HashMap::new(),
+            objects: Vec::new(),
+            changed_tiles: HashSet::new(),
+        }
+    }
+
+    pub fn contains(&self, tile: IVec2) -> bool {
+        tile.x >= 0 && tile.y >= 0 && (tile.x as u32) < self.width && (tile.y as u32) < self.height
+    }
+
+    pub fn is_solid(&self, tile: IVec2) -> bool {
+        self.contains(tile) && self.solid[self.tile_index(tile)]
+    }
+
+    // Whether a cell is solid and surrounded by cells that are completely solid, so nothing
+    // can reach it without hitting a neighbour first. Cells on the edge of the map never are.
+    pub fn is_enclosed(&self, tile: IVec2) -> bool {
+        let filled =
+            |tile: IVec2| self.is_solid(tile) && !self.partial_colliders.contains_key(&tile);
+        filled(tile)
+            && [IVec2::NEG_Y, IVec2::X, IVec2::Y, IVec2::NEG_X]
+                .into_iter()
+                .all(|offset| filled(tile + offset))
+    }
+
+    // Collider of a solid cell, relative to the center of the tile. Partial colliders are
+    // stored the way map files describe them, in map pixels with y pointing down.
+    pub fn collider(&self, tile: IVec2) -> Option<Collider> {
+        if !self.is_solid(tile) {
+            return None;
+        }
+        let Some(collider) = self.partial_colliders.get(&tile) else {
+            return Some(Collider::aabb(self.grid.tile_size / 2.));
+        };
+        let offset = match self.grid.rows {
+            RowDirection::Down => Vec2::new(collider.offset.x, -collider.offset.y),
+            RowDirection::Up => collider.offset,
+        };
+        Some(collider.with_offset(offset))
+    }
+
+    pub fn tile_to_world(&self, tile: IVec2) -> Vec2 {
+        self.grid.tile_to_world(tile)
+    }
+
+    // Tile under a world position, or None if it is outside the map
+    pub fn world_to_tile(&self, world: Vec2) -> Option<IVec2> {
+        let tile = self.grid.world_to_tile(world);
+        self.contains(tile).then_some(tile)
+    }
+
+    // Convert a position in map pixels (as stored in map files, y down) to world space
+    pub fn map_to_world(&self, position: Vec2) -> Vec2 {
+        self.grid.grid_to_world(position / self.grid.tile_size)
+    }
+
+    pub fn center(&self) -> Vec2 {
+        self.grid
+            .grid_to_world(Vec2::new(self.width as f32, self.height as f32) / 2.)
+    }
+
+    // World position of a "player" object, picked by name or the first one if no name is given
+    pub fn spawn_point(&self, name: Option<&str>) -> Option<Vec2> {
+        self.objects
+            .iter()
+            .filter(|object| object.kind == "player")
+            .find(|object| name.is_none_or(|name| object.name == name))
+            .map(|object| self.map_to_world(object.position))
+    }
+
+    // Row-major index of a tile into a layer or the solidity grid, the tile must be on the map
+    pub fn tile_index(&self, tile: IVec2) -> usize {
+        (tile.y as u32 * self.width + tile.x as u32) as usize
+    }
+
+    // Number of chunks across and down the map
+    pub fn chunk_count(&self) -> IVec2 {
+        IVec2::new(
+            self.width.div_ceil(CHUNK_SIZE) as i32,
+            self.height.div_ceil(CHUNK_SIZE) as i32,
+        )
+    }
+
+    pub fn tile_to_chunk(&self, tile: IVec2) -> IVec2 {
+        tile.div_euclid(IVec2::splat(CHUNK_SIZE as i32))
+    }
+
+    // Tiles covered by a chunk, clamped to the map
+    pub fn chunk_tiles(&self, chunk: IVec2) -> impl Iterator<Item = IVec2> {
+        let min = chunk * CHUNK_SIZE as i32;
+        let max = (min + CHUNK_SIZE as i32).min(IVec2::new(self.width as i32, self.height as i32));
+        (min.y..max.y).flat_map(move |y| (min.x..max.x).map(move |x| IVec2::new(x, y)))
+    }
+
+    // Distance from a world position to the closest point of a chunk
+    pub fn chunk_distance(&self, chunk: IVec2, world: Vec2) -> f32 {
+        let min = self
+            .grid
+            .grid_to_world((chunk * CHUNK_SIZE as i32).as_vec2());
+        let max = self
+            .grid
+            .grid_to_world(((chunk + 1) * CHUNK_SIZE as i32).as_vec2());
+        let center = (min + max) / 2.;
+        let half_size = (max - min).abs() / 2.;
+        ((world - center).abs() - half_size)
+            .max(Vec2::ZERO)
+            .length()
+    }
+
+    // Index of the first layer with the given name
+    pub fn layer_index(&self, name: &str) -> Option<usize> {
+        self.layers.iter().position(|layer| layer.name == name)
+    }
+
+    // Tile of a layer at a grid position, None if the cell is empty or outside the map
+    pub fn get_tile(&self, layer: usize, tile: IVec2) -> Option<Tile> {
+        if !self.contains(tile) {
+            return None;
+        }
+        self.layers.get(layer)?.tiles[self.tile_index(tile)]
+    }
+
+    // Replace the tile of a layer at a grid position, returning the tile that was there.
+    // Terrain tiles and their neighbours are autotiled again, the chunk is respawned
+    // before the frame is drawn. Solid tiles make their cell solid.
+    pub fn set_tile(&mut self, layer: usize, tile_pos: IVec2, tile: Tile) -> Option<Tile> {
+        self.replace_tile(layer, tile_pos, Some(tile))
+    }
+
+    // Clear the tile of a layer at a grid position, returning the tile that was there.
+    // Removing a solid tile leaves the cell solid only if another layer has one too.
+    pub fn remove_tile(&mut self, layer: usize, tile_pos: IVec2) -> Option<Tile> {
+        self.replace_tile(layer, tile_pos, None)
+    }
+
+    // Add or remove the collider of a cell, independent of the tiles drawn in it.
+    // Cells made solid this way are filled completely.
+    pub fn set_solid(&mut self, tile: IVec2, solid: bool) {
+        if !self.contains(tile) {
+            return;
+        }
+        let index = self.tile_index(tile);
+        let partial = self.partial_colliders.remove(&tile).is_some();
+        if self.solid[index] != solid || partial {
+            self.solid[index] = solid;
+            self.changed_tiles.insert(tile);
+        }
+    }
+
+    // Grip of the ground at a world position, 1 for plain ground. Ice is below 1, sticky
+    // ground above. The topmost tile with a traction of its own decides.
+    pub fn traction_at(&self, world: Vec2) -> f32 {
+        let Some(tile) = self.world_to_tile(world) else {
+            return 1.;
+        };
+        let index = self.tile_index(tile);
+        self.layers
+            .iter()
+            .rev()
+            .filter_map(|layer| layer.tiles[index])
+            .find_map(|tile| {
+                self.tilesets[tile.tileset]
+                    .traction
+                    .get(&tile.index)
+                    .copied()
+            })
+            .unwrap_or(1.)
+    }
+
+    // Solid cells are walls unless one of their tiles is water
+    pub fn collision_layer(&self, tile: IVec2) -> Layer {
+        let index = self.tile_index(tile);
+        let water = self.layers.iter().any(|layer| {
+            layer.tiles[index].is_some_and(|tile| tile.terrain == Some(Terrain::Water))
+        });
+        if water {
+            Layer::Water
+        } else {
+            Layer::Wall
+        }
+    }
+
+    // Make a cell solid with a collider that covers only part of it, e.g. a half wall
+    pub fn set_partial_collider(&mut self, tile: IVec2, collider: Collider) {
+        if !self.contains(tile) {
+            return;
+        }
+        let index = self.tile_index(tile);
+        self.solid[index] = true;
+        self.partial_colliders.insert(tile, collider);
+        self.changed_tiles.insert(tile);
+    }
+
+    fn replace_tile(&mut self, layer: usize, tile_pos: IVec2, tile: Option<Tile>) -> Option<Tile> {
+        if !self.contains(tile_pos) || layer >= self.layers.len() {
+            warn!("No tile at {} in layer {}", tile_pos, layer);
+            return None;
+        }
+        let index = self.tile_index(tile_pos);
+        let previous = std::mem::replace(&mut self.layers[layer].tiles[index], tile);
+
+        // Cells that are solid without a solid tile (collision layers, set_solid) are left as
+        // they are unless a solid tile is placed or removed
+        let has_collider = |tile: Option<Tile>| tile.is_some_and(|tile| tile.collider.is_some());
+        if has_collider(previous) || has_collider(tile) {
+            self.update_solidity(tile_pos);
+        }
+
+        self.changed_tiles.insert(tile_pos);
+        let autotiled = self.autotile_around(tile_pos);
+        self.changed_tiles.extend(autotiled);
+        previous
+    }
+
+    // The topmost solid tile of a cell decides its collider, without one the cell is open
+    fn update_solidity(&mut self, tile_pos: IVec2) {
+        let index = self.tile_index(tile_pos);
+        let collider = self
+            .layers
+            .iter()
+            .rev()
+            .filter_map(|layer| layer.tiles[index])
+            .find_map(|tile| tile.collider);
+        match collider {
+            Some(collider) if collider == Collider::aabb(self.grid.tile_size / 2.) => {
+                self.set_solid(tile_pos, true)
+            }
+            Some(collider) => self.set_partial_collider(tile_pos, collider),
+            None => self.set_solid(tile_pos, false),
+        }
+    }
+
+    // Tiles changed since the last call
+    pub fn take_changed_tiles(&mut self) -> HashSet<IVec2> {
+        std::mem::take(&mut self.changed_tiles)
+    }
+
+    pub fn has_changed_tiles(&self) -> bool {
+        !self.changed_tiles.is_empty()
+    }
+
+    pub fn is_animated(&self, tile: &Tile) -> bool {
+        self.tilesets[tile.tileset].is_animated(tile.index)
+    }
+
+    // Coordinates of the tile at a row-major index into a layer or the solidity grid
+    pub fn index_to_tile(&self, index: usize) -> IVec2 {
+        IVec2::new(
+            (index as u32 % self.width) as i32,
+            (index as u32 / self.width) as i32,
+        )
+    }
+}
+
+impl TilesetDescription {
+    pub fn tile_count(&self) -> u32 {
+        (self.columns * self.rows) as u32
+    }
+
+    pub fn layout(&self) -> TextureAtlasLayout {
+        TextureAtlasLayout::from_grid(
+            self.tile_size,
+            self.columns,
+            self.rows,
+            Some(self.padding),
+            Some(self.offset),
+        )
+    }
+
+    // Load the tileset image and add its layout to the map asset under the given label
+    pub fn load(&self, load_context: &mut LoadContext, label: String) -> Tileset {
+        Tileset {
+            name: self.name.clone(),
+            texture: load_context
+                .load_with_settings(self.image.clone(), |settings: &mut ImageLoaderSettings| {
+                    settings.sampler = ImageSampler::nearest()
+                }),
+            layout: load_context.add_labeled_asset(label, self.layout()),
+            animations: HashMap::new(),
+            autotiles: HashMap::new(),
+            traction: HashMap::new(),
+        }
+    }
+
+    // Find the tileset a GID belongs to, returning its index and the local tile index
+    pub fn find(tilesets: &[TilesetDescription], gid: u32) -> Option<(usize, usize)> {
+        tilesets
+            .iter()
+            .enumerate()
+            .filter(|(_, tileset)| tileset.first_gid <= gid)
+            .max_by_key(|(_, tileset)| tileset.first_gid)
+            .filter(|(_, tileset)| gid - tileset.first_gid < tileset.tile_count())
+            .map(|(i, tileset)| (i, (gid - tileset.first_gid) as usize))
+    }
+
+    // Check that no two tilesets claim the same GIDs
+    pub fn check_overlap(tilesets: &[TilesetDescription]) -> Result<(), TileMapLoaderError> {
+        let mut sorted: Vec<&TilesetDescription> = tilesets.iter().collect();
+        sorted.sort_by_key(|tileset| tileset.first_gid);
+        for pair in sorted.windows(2) {
+            if pair[0].first_gid + pair[0].tile_count() > pair[1].first_gid {
+                return Err(TileMapLoaderError::Invalid(format!(
+                    "tilesets '{}' and '{}' overlap, '{}' should start at GID {} or later",
+                    pair[0].name,
+                    pair[1].name,
+                    pair[1].name,
+                    pair[0].first_gid + pair[0].tile_count()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Tileset {
+    pub fn is_animated(&self, index: usize) -> bool {
+        self.animations.contains_key(&index)
+    }
+
+    // Terrain a tile belongs to if it is one of the variants of an autotile rule
+    pub fn terrain_of(&self, index: usize) -> Option<Terrain> {
+        self.autotiles
+            .iter()
+            .find(|(_, rule)| rule.contains(index))
+            .map(|(terrain, _)| *terrain)
+    }
+
+    // Tile index to draw for a tile at a point in time, static tiles are always themselves
+    pub fn frame(&self, index: usize, time: f32) -> usize {
+        self.animations
+            .get(&index)
+            .map_or(index, |animation| animation.frame_at(time))
+    }
+}
+
+impl Tile {
+    // A plain tile as drawn in its tileset, without flips, tint, terrain or collider
+    pub fn new(tileset: usize, index: usize) -> Self {
+        Tile {
+            tileset,
+            index,
+            flip_x: false,
+            flip_y: false,
+            rotation: 0.,
+            color: Color::WHITE,
+            terrain: None,
+            collider: None,
+        }
+    }
+}
+
+impl TileAnimation {
+    // Tile index shown at a point in time, animations loop forever
+    pub fn frame_at(&self, time: f32) -> usize {
+        let total: f32 = self.frames.iter().map(|frame| frame.duration).sum();
+        let mut time = if total > 0. {
+            time.rem_euclid(total)
+        } else {
+            0.
+        };
+        for frame in self.frames.iter() {
+            if time < frame.duration {
+                return frame.index;
+            }
+            time -= frame.duration;
+        }
+        self.frames.last().map_or(0, |frame| frame.index)
+    }
+}
+
+impl MapObject {
+    pub fn property_bool(&self, name: &str) -> Option<bool> {
+        match self.properties.get(name) {
+            Some(PropertyValue::Bool(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn property_str(&self, name: &str) -> Option<&str> {
+        match self.properties.get(name) {
+            Some(PropertyValue::String(value)) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+// Start loading the first map so the Preload state can wait for it
+fn load_map(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(CurrentMap(asset_server.load(START_MAP)));
+}
+
+fn spawn_map_system(
+    mut commands: Commands,
+    current_map: Res<CurrentMap>,
+    maps: Res<Assets<TileMap>>,
+    registry: Res<ObjectRegistry>,
+    asset_server: Res<AssetServer>,
+) {
+    // Preload only advances once the map is loaded, so it is always available here
+    let map = maps.get(&current_map.0).expect("Map is not loaded");
+    spawn_map(&mut commands, map, &registry, &asset_server);
+}
+
+// Respawn the map whenever its file changes on disk
+fn reload_map_system(
     mut commands: Commands,
-    ascii: Res<AsciiSheet>,
-    mut texture_atlases: ResMut<Assets<TextureAtlasLayout>>,
-    win_size: Res<WinSize>,
+    mut events: EventReader<AssetEvent<TileMap>>,
+    current_map: Res<CurrentMap>,
+    maps: Res<Assets<TileMap>>,
+    registry: Res<ObjectRegistry>,
+    asset_server: Res<AssetServer>,
+    map_query: Query<Entity, With<MapRoot>>,
 ) {
-    let file = File::open("assets/tilemap/Ascii.txt").expect("File not found");
+    for event in events.read() {
+        if !event.is_modified(&current_map.0) {
+            continue;
+        }
+        let Some(map) = maps.get(&current_map.0) else {
+            continue;
+        };
+
+        for entity in map_query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        spawn_map(&mut commands, map, &registry, &asset_server);
+        info!("Reloaded map {:?}", current_map.0.path());
+    }
+}
+
+pub fn spawn_map(
+    commands: &mut Commands,
+    map: &TileMap,
+    registry: &ObjectRegistry,
+    asset_server: &AssetServer,
+) -> Entity {
+    // Tiles are spawned chunk by chunk around the camera by chunk_streaming_system, colliders
+    // are all spawned now so walls work wherever movers are. Only the walls movers can touch
+    // get one, so solid rock between rooms costs nothing.
     let mut tiles: Vec<Entity> = Vec::new();
+    commands.insert_resource(LoadedChunks::default());
+    commands.insert_resource(TileMaterials::default());
 
-    // Tile size, rows, columns, and padding are hardcoded here and specific to this tilemap image
-    let layout =
-        TextureAtlasLayout::from_grid(Vec2::new(9., 9.), 16, 16, Some(Vec2::new(2., 2.)), None);
-    let texture_atlas_layout = texture_atlases.add(layout);
-
-    for (y, line) in BufReader::new(file).lines().enumerate() {
-        if let Ok(line) = line {
-            for (x, char) in line.chars().enumerate() {
-                let tile = spawn_ascii_sprite(
-                    &mut commands,
-                    &ascii,
-                    char as usize,
-                    Color::rgb(0.9, 0.9, 0.9),
-                    Vec3::new(
-                        x as f32 * TILE_SIZE - win_size.w / 2. + 16.,
-                        y as f32 * TILE_SIZE - win_size.h / 2. + 8.,
-                        0.,
-                    ),
-                    texture_atlas_layout.clone(),
-                );
-                if char == '#' {
-                    commands.entity(tile).insert(TileCollider); // Inserts a tilecollider component to this entity
-                }
-                tiles.push(tile);
-            }
+    let mut colliders = MapColliders::default();
+    for index in 0..map.solid.len() {
+        let tile_pos = map.index_to_tile(index);
+        if let Some(entity) = spawn_tile_collider(commands, map, tile_pos) {
+            colliders.0.insert(tile_pos, entity);
+            tiles.push(entity);
+        }
+    }
+    commands.insert_resource(colliders);
+
+    // Player objects are only spawn points, read through TileMap::spawn_point
+    for object in map.objects.iter().filter(|object| object.kind != "player") {
+        let translation = map.map_to_world(object.position).extend(0.);
+        if let Some(entity) = registry.spawn(commands, asset_server, object, translation) {
+            tiles.push(entity);
         }
     }
+
+    // The spawned map becomes the TileMap resource gameplay uses to find its way around
+    commands.insert_resource(map.clone());
+
     commands
         .spawn(SpatialBundle {
             // Use SpatialBundle instead of TransformBundle becaause of difference in InheritedVisibility between parent and child
@@ -58,5 +707,168 @@ fn spawn_ascii_map(
             ..default()
         })
         .insert(Name::new("Map"))
-        .push_children(&tiles);
+        .insert(MapRoot)
+        .push_children(&tiles)
+        .id()
+}
+
+// Exits are invisible trigger areas, the map and spawn point to go to are object properties
+fn spawn_exit(entity: &mut EntityCommands, object: &MapObject, _asset_server: &AssetServer) {
+    entity.insert((
+        LevelExit {
+            destination: object.property_str("map").map(str::to_string),
+            spawn_point: object.property_str("spawn").map(str::to_string),
+        },
+        Collider::aabb(object.size / 2.),
+        Sensor,
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map_with_layers(layers: usize) -> TileMap {
+        let mut map = TileMap::new(4, 4, Vec2::splat(32.));
+        for layer in 0..layers {
+            map.layers.push(TileLayer {
+                name: format!("layer {}", layer),
+                tiles: vec![None; 16],
+                visible: true,
+                opacity: 1.,
+            });
+        }
+        map
+    }
+
+    fn tile(index: usize) -> Tile {
+        Tile::new(0, index)
+    }
+
+    fn wall(collider: Collider) -> Tile {
+        Tile {
+            collider: Some(collider),
+            ..Tile::new(0, 0)
+        }
+    }
+
+    fn index(tile: Option<Tile>) -> Option<usize> {
+        tile.map(|tile| tile.index)
+    }
+
+    #[test]
+    fn set_and_remove_return_the_tile_that_was_there() {
+        let mut map = map_with_layers(2);
+        let cell = IVec2::new(1, 2);
+
+        assert_eq!(index(map.set_tile(1, cell, tile(5))), None);
+        assert_eq!(index(map.get_tile(1, cell)), Some(5));
+        assert_eq!(index(map.get_tile(0, cell)), None);
+        assert_eq!(index(map.set_tile(1, cell, tile(6))), Some(5));
+        assert_eq!(index(map.remove_tile(1, cell)), Some(6));
+        assert_eq!(index(map.get_tile(1, cell)), None);
+        assert_eq!(map.layer_index("layer 1"), Some(1));
+        assert_eq!(map.layer_index("roof"), None);
+    }
+
+    #[test]
+    fn tiles_outside_the_map_are_left_alone() {
+        let mut map = map_with_layers(1);
+        assert_eq!(index(map.set_tile(0, IVec2::new(4, 0), tile(1))), None);
+        assert_eq!(index(map.set_tile(1, IVec2::ZERO, tile(1))), None);
+        assert_eq!(index(map.get_tile(0, IVec2::new(-1, 0))), None);
+        assert_eq!(index(map.get_tile(2, IVec2::ZERO)), None);
+        assert!(!map.has_changed_tiles());
+    }
+
+    #[test]
+    fn changes_are_collected_until_taken() {
+        let mut map = map_with_layers(1);
+        map.set_tile(0, IVec2::new(0, 0), tile(1));
+        map.remove_tile(0, IVec2::new(3, 3));
+        map.set_solid(IVec2::new(2, 1), true);
+        map.set_solid(IVec2::new(2, 2), false); // Already open
+        assert_eq!(
+            map.take_changed_tiles(),
+            HashSet::from([IVec2::new(0, 0), IVec2::new(3, 3), IVec2::new(2, 1)])
+        );
+        assert!(map.is_solid(IVec2::new(2, 1)));
+        assert!(!map.has_changed_tiles());
+    }
+
+    #[test]
+    fn placing_and_removing_solid_tiles_updates_the_cell() {
+        let mut map = map_with_layers(1);
+        let cell = IVec2::new(1, 2);
+        map.set_tile(0, cell, wall(Collider::aabb(Vec2::splat(16.))));
+        assert!(map.is_solid(cell));
+        assert_eq!(map.collider(cell), Some(Collider::aabb(Vec2::splat(16.))));
+
+        map.remove_tile(0, cell);
+        assert!(!map.is_solid(cell));
+        assert_eq!(map.collider(cell), None);
+    }
+
+    #[test]
+    fn solid_tiles_with_a_shape_make_partial_colliders() {
+        let mut map = map_with_layers(1);
+        let cell = IVec2::new(1, 1);
+        let half_wall = Collider::aabb(Vec2::new(16., 8.)).with_offset(Vec2::new(0., 8.));
+
+        map.set_tile(0, cell, wall(half_wall));
+        assert_eq!(map.partial_colliders.get(&cell), Some(&half_wall));
+
+        // Replacing it with a full wall fills the cell again
+        map.set_tile(0, cell, wall(Collider::aabb(Vec2::splat(16.))));
+        assert!(map.is_solid(cell));
+        assert!(map.partial_colliders.is_empty());
+    }
+
+    #[test]
+    fn removing_a_solid_tile_keeps_solid_tiles_below() {
+        let mut map = map_with_layers(2);
+        let cell = IVec2::new(2, 2);
+        map.set_tile(0, cell, wall(Collider::aabb(Vec2::splat(16.))));
+        map.set_tile(1, cell, wall(Collider::aabb(Vec2::splat(8.))));
+
+        map.remove_tile(1, cell);
+        assert!(map.is_solid(cell));
+        assert!(map.partial_colliders.is_empty());
+    }
+
+    #[test]
+    fn open_tiles_leave_cells_solid_for_other_reasons() {
+        let mut map = map_with_layers(1);
+        let cell = IVec2::new(3, 0);
+        map.set_solid(cell, true);
+
+        map.set_tile(0, cell, tile(0));
+        map.remove_tile(0, cell);
+        assert!(map.is_solid(cell));
+
+        // A floor tile placed where a wall tile was opens the cell
+        let open = IVec2::new(0, 3);
+        map.set_tile(0, open, wall(Collider::aabb(Vec2::splat(16.))));
+        map.set_tile(0, open, tile(0));
+        assert!(!map.is_solid(open));
+        assert!(map.take_changed_tiles().contains(&open));
+    }
+
+    #[test]
+    fn only_solid_cells_next_to_open_ones_can_be_touched() {
+        let mut map = map_with_layers(0);
+        map.solid = vec![true; 16];
+        let center = IVec2::new(1, 1);
+        assert!(map.is_enclosed(center));
+        assert!(!map.is_enclosed(IVec2::new(1, 0))); // On the edge of the map
+
+        // A neighbour that is open or only partly solid exposes the cell
+        map.partial_colliders
+            .insert(IVec2::new(2, 1), Collider::aabb(Vec2::splat(8.)));
+        assert!(!map.is_enclosed(center));
+        map.partial_colliders.clear();
+        map.set_solid(IVec2::new(1, 2), false);
+        assert!(!map.is_enclosed(center));
+        assert!(!map.is_enclosed(IVec2::new(1, 2)));
+    }
 }